@@ -1,17 +1,169 @@
-use crate::note::NoteBlock;
+use crate::{
+    note::NoteBlock,
+    resample::{PitchCache, ResampleQuality},
+    sink::{NoteEvent, NoteSink},
+};
 use kira::{
     self, AudioManager, AudioManagerSettings, Decibels, DefaultBackend, Panning, PlaybackRate,
-    sound::static_sound::{StaticSoundData, StaticSoundSettings},
+    Tween,
+    sound::{
+        PlaybackState,
+        static_sound::{StaticSoundData, StaticSoundHandle, StaticSoundSettings},
+    },
     track::{TrackBuilder, TrackHandle},
 };
 
-use std::{collections::HashMap, io::Cursor, vec};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Cursor,
+    time::Duration,
+};
+
+/// Pitched variants rendered per frame in high quality mode, so the window and audio keep
+/// running while a song is prepared
+const PREPARE_BATCH: usize = 4;
+
+/// Limits how long the notes of an instrument keep ringing
+#[derive(Clone, Copy, Debug)]
+pub struct InstrumentEnvelope {
+    /// Maximum time a note plays before being faded out, in seconds
+    pub max_duration: Option<f64>,
+    /// Fade-out time used when a note is stopped early, in seconds
+    pub release: f64,
+}
+
+impl Default for InstrumentEnvelope {
+    fn default() -> Self {
+        Self {
+            max_duration: None,
+            release: 0.05,
+        }
+    }
+}
+
+impl InstrumentEnvelope {
+    /// Whether a note playing for `elapsed` seconds has to be faded out
+    pub fn is_expired(&self, elapsed: f64) -> bool {
+        self.max_duration
+            .is_some_and(|max_duration| elapsed >= max_duration)
+    }
+
+    /// Fade-out of stopped notes, negative releases stopping them at once
+    pub fn release_tween(&self) -> Tween {
+        Tween {
+            duration: Duration::from_secs_f64(self.release.max(0.)),
+            ..Default::default()
+        }
+    }
+}
+
+/// Samples of the vanilla instruments, in instrument id order
+pub const VANILLA_SOUNDS: [&[u8]; 16] = [
+    include_bytes!("../assets/sounds/harp.ogg"),
+    include_bytes!("../assets/sounds/bass.ogg"),
+    include_bytes!("../assets/sounds/bd.ogg"),
+    include_bytes!("../assets/sounds/snare.ogg"),
+    include_bytes!("../assets/sounds/hat.ogg"),
+    include_bytes!("../assets/sounds/guitar.ogg"),
+    include_bytes!("../assets/sounds/flute.ogg"),
+    include_bytes!("../assets/sounds/bell.ogg"),
+    include_bytes!("../assets/sounds/icechime.ogg"),
+    include_bytes!("../assets/sounds/xylobone.ogg"),
+    include_bytes!("../assets/sounds/iron_xylophone.ogg"),
+    include_bytes!("../assets/sounds/cow_bell.ogg"),
+    include_bytes!("../assets/sounds/didgeridoo.ogg"),
+    include_bytes!("../assets/sounds/bit.ogg"),
+    include_bytes!("../assets/sounds/banjo.ogg"),
+    include_bytes!("../assets/sounds/pling.ogg"),
+];
+
+/// Base key of the vanilla instrument samples (F#4)
+pub const VANILLA_SOUND_KEY: f64 = 45.;
+
+/// Id of the sound of an instrument in a song whose custom instruments start at
+/// `first_custom`. Custom sounds are stored after every vanilla sound, so songs saved with
+/// fewer vanilla instruments don't replace the newer ones.
+pub fn sound_id(instrument: u8, first_custom: u8) -> u32 {
+    if instrument < first_custom {
+        instrument as u32
+    } else {
+        (VANILLA_SOUNDS.len() + (instrument - first_custom) as usize) as u32
+    }
+}
+
+/// Numbers the groups of notes triggered together, which are the notes of one tick played
+/// in one frame. Song times can't order notes across seeks, loops and song changes.
+#[derive(Default)]
+struct TriggerBatches {
+    tick: Option<usize>,
+    count: u64,
+}
+
+impl TriggerBatches {
+    /// Batch of a note triggered now
+    fn next(&mut self, tick: usize) -> u64 {
+        if self.tick != Some(tick) {
+            self.tick = Some(tick);
+            self.count += 1;
+        }
+        self.count
+    }
+
+    /// Starts a new batch with the next note, even on the same tick
+    fn end_batch(&mut self) {
+        self.tick = None;
+    }
+}
+
+/// What cut-previous mode compares to decide which ringing notes a new note stops
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct NoteTrigger {
+    /// Sound played, which keys the sounds and envelopes like `sound_id`
+    sound_id: u32,
+    key: u8,
+    batch: u64,
+}
+
+impl NoteTrigger {
+    /// Trigger of a note in a song whose custom instruments start at `first_custom`
+    fn new(note: &NoteEvent, first_custom: u8, batch: u64) -> Self {
+        Self {
+            sound_id: sound_id(note.instrument, first_custom),
+            key: note.key,
+            batch,
+        }
+    }
+
+    /// Whether `next` cuts this note: same instrument and key, triggered in another batch.
+    /// Notes of the same tick ring together.
+    fn is_cut_by(&self, next: &NoteTrigger) -> bool {
+        self.sound_id == next.sound_id && self.key == next.key && self.batch != next.batch
+    }
+}
+
+/// A sound that is still playing, kept so it can be cut or faded out
+struct ActiveNote {
+    trigger: NoteTrigger,
+    started_at: f64,
+    handle: StaticSoundHandle,
+}
 
 pub struct AudioEngine {
     _manager: AudioManager<DefaultBackend>,
     sounds: HashMap<u32, (StaticSoundData, f64)>,
+    /// Instrument id of the current song's first custom instrument
+    first_custom: u8,
     global_volume: f32,
     main_track: TrackHandle,
+    envelopes: HashMap<u32, InstrumentEnvelope>,
+    cut_previous: bool,
+    active_notes: Vec<ActiveNote>,
+    trigger_batches: TriggerBatches,
+    clock: f64,
+    quality: ResampleQuality,
+    pitch_cache: PitchCache,
+    /// Instrument and cents of the variants left to render
+    pending_variants: Vec<(u32, i32)>,
 }
 
 impl AudioEngine {
@@ -21,40 +173,17 @@ impl AudioEngine {
         sound_data
     }
 
-    pub fn new(extra_sounds: Option<Vec<(&[u8], f64)>>, global_volume: f32) -> Self {
+    pub fn new(global_volume: f32) -> Self {
         let mut manager =
             AudioManager::<DefaultBackend>::new(AudioManagerSettings::default()).unwrap();
 
-        let data = vec![
-            include_bytes!("../assets/sounds/harp.ogg") as &[u8],
-            include_bytes!("../assets/sounds/bass.ogg") as &[u8],
-            include_bytes!("../assets/sounds/bd.ogg") as &[u8],
-            include_bytes!("../assets/sounds/snare.ogg") as &[u8],
-            include_bytes!("../assets/sounds/hat.ogg") as &[u8],
-            include_bytes!("../assets/sounds/guitar.ogg") as &[u8],
-            include_bytes!("../assets/sounds/flute.ogg") as &[u8],
-            include_bytes!("../assets/sounds/bell.ogg") as &[u8],
-            include_bytes!("../assets/sounds/icechime.ogg") as &[u8],
-            include_bytes!("../assets/sounds/xylobone.ogg") as &[u8],
-            include_bytes!("../assets/sounds/iron_xylophone.ogg") as &[u8],
-            include_bytes!("../assets/sounds/cow_bell.ogg") as &[u8],
-            include_bytes!("../assets/sounds/didgeridoo.ogg") as &[u8],
-            include_bytes!("../assets/sounds/bit.ogg") as &[u8],
-            include_bytes!("../assets/sounds/banjo.ogg") as &[u8],
-            include_bytes!("../assets/sounds/pling.ogg") as &[u8],
-        ];
-
-        let mut sound_files = data.iter().map(|data| (*data, 45.)).collect::<Vec<_>>();
-
-        if let Some(extra_sounds) = extra_sounds {
-            log::info!("Loaded {} extra sounds", extra_sounds.len());
-            sound_files.extend(extra_sounds);
-        }
-
         let mut sounds = HashMap::new();
 
-        for (i, sound) in sound_files.iter().enumerate() {
-            sounds.insert(i as u32, (Self::load_sound_data(sound.0.to_vec()), sound.1));
+        for (i, sound) in VANILLA_SOUNDS.iter().enumerate() {
+            sounds.insert(
+                i as u32,
+                (Self::load_sound_data(sound.to_vec()), VANILLA_SOUND_KEY),
+            );
         }
 
         log::info!("Loaded {} sounds", sounds.len());
@@ -66,11 +195,191 @@ impl AudioEngine {
             main_track,
             _manager: manager,
             sounds,
+            first_custom: VANILLA_SOUNDS.len() as u8,
             global_volume,
+            envelopes: HashMap::new(),
+            cut_previous: false,
+            active_notes: Vec::new(),
+            trigger_batches: TriggerBatches::default(),
+            clock: 0.,
+            quality: ResampleQuality::Fast,
+            pitch_cache: PitchCache::default(),
+            pending_variants: Vec::new(),
+        }
+    }
+
+    /// Replaces the custom instrument sounds with the sounds of another song, whose custom
+    /// instruments start at `first_custom`, skipping the instruments without a sound
+    pub fn set_extra_sounds(&mut self, first_custom: u8, extra_sounds: &[Option<(Vec<u8>, f64)>]) {
+        self.stop_all();
+
+        let vanilla_count = VANILLA_SOUNDS.len() as u32;
+        self.sounds.retain(|id, _| *id < vanilla_count);
+        self.envelopes.retain(|id, _| *id < vanilla_count);
+        self.pitch_cache.clear();
+        self.pending_variants.clear();
+
+        for (i, sound) in extra_sounds.iter().enumerate() {
+            if let Some((data, key)) = sound {
+                self.sounds.insert(
+                    vanilla_count + i as u32,
+                    (Self::load_sound_data(data.clone()), *key),
+                );
+            }
+        }
+        self.first_custom = first_custom;
+        log::info!(
+            "Loaded {} extra sounds",
+            extra_sounds.iter().flatten().count()
+        );
+    }
+
+    /// Sets the note volume from 0 to 1, for the notes played from now on
+    pub fn set_volume(&mut self, volume: f32) {
+        if volume.is_finite() {
+            self.global_volume = volume.clamp(0., 1.);
         }
     }
 
-    fn get_sound_data(&mut self, note: &NoteBlock) -> Option<StaticSoundData> {
+    pub fn volume(&self) -> f32 {
+        self.global_volume
+    }
+
+    pub fn set_quality(&mut self, quality: ResampleQuality) {
+        self.quality = quality;
+        if quality == ResampleQuality::Fast {
+            self.pitch_cache.clear();
+            self.pending_variants.clear();
+        }
+    }
+
+    pub fn quality(&self) -> ResampleQuality {
+        self.quality
+    }
+
+    /// Pitch offset of a note from its instrument's base key, in cents
+    pub fn note_cents(key: u8, pitch: i16, tone: f64) -> i32 {
+        ((key as f64 - tone) * 100.0 + pitch as f64).round() as i32
+    }
+
+    /// Queues the pitched variants used by `note_blocks`, which `update` renders a few at a
+    /// time. Notes fall back to the fast pitching until their variant is ready.
+    pub fn prepare_notes(&mut self, note_blocks: &[Vec<NoteBlock>]) {
+        if self.quality != ResampleQuality::HighQuality {
+            return;
+        }
+
+        let mut queued: HashSet<(u32, i32)> = self.pending_variants.iter().copied().collect();
+        for note in note_blocks.iter().flatten() {
+            let sound_id = sound_id(note.instrument, self.first_custom);
+            if let Some((_, tone)) = self.sounds.get(&sound_id) {
+                let cents = Self::note_cents(note.key, note.pitch, *tone);
+                if cents != 0
+                    && !self.pitch_cache.contains(sound_id, cents)
+                    && queued.insert((sound_id, cents))
+                {
+                    self.pending_variants.push((sound_id, cents));
+                }
+            }
+        }
+    }
+
+    /// Number of pitched variants still being rendered
+    pub fn pending_variants(&self) -> usize {
+        self.pending_variants.len()
+    }
+
+    /// Renders the next few queued variants
+    fn render_pending(&mut self) {
+        if self.pending_variants.is_empty() {
+            return;
+        }
+
+        // In song order, so the first notes are ready first
+        let batch = PREPARE_BATCH.min(self.pending_variants.len());
+        let variants: Vec<(u32, i32)> = self.pending_variants.drain(..batch).collect();
+        for (sound_id, cents) in variants {
+            if let Some((data, _)) = self.sounds.get(&sound_id) {
+                self.pitch_cache
+                    .get_or_render(sound_id, cents, &data.frames);
+            }
+        }
+
+        if self.pending_variants.is_empty() {
+            log::info!("Prepared {} pitched variants", self.pitch_cache.len());
+        }
+    }
+
+    /// Sets the envelope of a single sound, as numbered by `sound_id`. `None` lets the full
+    /// sample play.
+    pub fn set_envelope(&mut self, sound_id: u32, envelope: Option<InstrumentEnvelope>) {
+        match envelope {
+            Some(envelope) => self.envelopes.insert(sound_id, envelope),
+            None => self.envelopes.remove(&sound_id),
+        };
+    }
+
+    pub fn has_envelopes(&self) -> bool {
+        !self.envelopes.is_empty()
+    }
+
+    /// Sounds longer than `min_duration` seconds, as numbered by `sound_id`
+    pub fn long_sample_instruments(&self, min_duration: f64) -> Vec<u32> {
+        let mut instruments: Vec<u32> = self
+            .sounds
+            .iter()
+            .filter(|(_, (data, _))| {
+                data.frames.len() as f64 / data.sample_rate as f64 > min_duration
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        instruments.sort();
+        instruments
+    }
+
+    /// When enabled, a note cuts the previous note of the same instrument and key, like NBS does
+    pub fn set_cut_previous(&mut self, cut_previous: bool) {
+        self.cut_previous = cut_previous;
+    }
+
+    pub fn cut_previous(&self) -> bool {
+        self.cut_previous
+    }
+
+    fn envelope(&self, instrument: u32) -> InstrumentEnvelope {
+        self.envelopes.get(&instrument).copied().unwrap_or_default()
+    }
+
+    /// Advances the engine clock, fading out notes that exceeded their maximum duration
+    pub fn update(&mut self, delta_time: f32) {
+        self.clock += delta_time as f64;
+        self.trigger_batches.end_batch();
+        self.render_pending();
+
+        for i in 0..self.active_notes.len() {
+            let envelope = self.envelope(self.active_notes[i].trigger.sound_id);
+            let note = &mut self.active_notes[i];
+            if envelope.is_expired(self.clock - note.started_at)
+                && note.handle.state() == PlaybackState::Playing
+            {
+                note.handle.stop(envelope.release_tween());
+            }
+        }
+
+        self.active_notes
+            .retain(|note| note.handle.state() != PlaybackState::Stopped);
+    }
+
+    /// Fades out every playing note
+    pub fn stop_all(&mut self) {
+        for i in 0..self.active_notes.len() {
+            let envelope = self.envelope(self.active_notes[i].trigger.sound_id);
+            self.active_notes[i].handle.stop(envelope.release_tween());
+        }
+        self.active_notes.clear();
+    }
+
+    fn get_sound_data(&mut self, note: &NoteEvent) -> Option<StaticSoundData> {
         // Fast approximation for 2^x
         fn fast_pow2(x: f32) -> f32 {
             let x0 = x.floor();
@@ -99,10 +408,9 @@ impl AudioEngine {
         const EPOCH: f32 = 1e-6;
 
         // Extract note properties
-        let sound_id = note.instrument as u32;
+        let sound_id = sound_id(note.instrument, self.first_custom);
         let key = note.key as f32; // Use f32 directly
         let velocity = note.velocity as f32;
-        let panning = note.panning as f32;
         let pitch = note.pitch as f32; // Use f32 directly
 
         // Fetch sound data
@@ -114,26 +422,41 @@ impl AudioEngine {
             }
         };
 
+        let cents = Self::note_cents(note.key, note.pitch, sound_data.1);
+
+        // Use a pre-rendered pitched variant in high quality mode once it's ready, kira's playback
+        // rate otherwise
+        let prerendered = match self.quality {
+            ResampleQuality::HighQuality if cents != 0 => self.pitch_cache.get(sound_id, cents),
+            _ => None,
+        };
+        let (frames, playback_rate) = if let Some(frames) = prerendered {
+            (frames, PlaybackRate(1.0))
+        } else {
+            // Calculate frequency ratio using fast approximation
+            let tone = sound_data.1 as f32;
+            let frequency_ratio = fast_pow2((key + (pitch / 100.0) - tone) * INV_12);
+            (
+                sound_data.0.frames.clone(),
+                PlaybackRate(frequency_ratio as f64),
+            )
+        };
+
         // Clone sound data
         let sound = StaticSoundData {
             sample_rate: sound_data.0.sample_rate,
-            frames: sound_data.0.frames.clone(),
+            frames,
             settings: StaticSoundSettings::default(),
             slice: None,
         };
 
-        // Calculate frequency ratio using fast approximation
-        let tone = sound_data.1 as f32;
-        let frequency_ratio = fast_pow2((key + (pitch / 100.0) - tone) * INV_12);
-        let playback_rate = PlaybackRate(frequency_ratio as f64);
-
         // Calculate volume in decibels
         let volume = Decibels::from(
             10.0 * ((velocity * self.global_volume + EPOCH) / (100.0 + EPOCH)).log10(),
         );
 
         // Calculate panning
-        let pan = Panning((panning / 100.0) - 1.0);
+        let pan = Panning(note.stereo_position());
 
         // Apply settings
         let settings = StaticSoundSettings::default()
@@ -145,13 +468,121 @@ impl AudioEngine {
         Some(sound.clone().with_settings(settings))
     }
 
-    pub fn play_tick(&mut self, notes: &[NoteBlock]) {
-        for note in notes {
-            if let Some(sound) = self.get_sound_data(note) {
-                if let Err(e) = self.main_track.play(sound) {
-                    log::error!("Failed to play sound: {}", e);
-                }
+    /// Cuts the ringing notes a new note replaces
+    fn cut_notes(&mut self, next: &NoteTrigger) {
+        let release = self.envelope(next.sound_id).release_tween();
+        for note in self.active_notes.iter_mut() {
+            if note.trigger.is_cut_by(next) {
+                note.handle.stop(release);
             }
         }
     }
 }
+
+/// Plays notes as they are triggered, ignoring their time
+impl NoteSink for AudioEngine {
+    fn trigger_note(&mut self, note: &NoteEvent) {
+        let trigger = NoteTrigger::new(
+            note,
+            self.first_custom,
+            self.trigger_batches.next(note.tick),
+        );
+        if self.cut_previous {
+            self.cut_notes(&trigger);
+        }
+
+        if let Some(sound) = self.get_sound_data(note) {
+            match self.main_track.play(sound) {
+                Ok(handle) => self.active_notes.push(ActiveNote {
+                    trigger,
+                    started_at: self.clock,
+                    handle,
+                }),
+                Err(e) => log::error!("Failed to play sound: {}", e),
+            }
+        }
+    }
+
+    fn stop_all(&mut self) {
+        AudioEngine::stop_all(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(instrument: u8, key: u8) -> NoteEvent {
+        NoteEvent {
+            tick: 0,
+            time: 0.,
+            instrument,
+            key,
+            velocity: 100,
+            panning: 100,
+            pitch: 0,
+        }
+    }
+
+    fn note(instrument: u8, key: u8, batch: u64) -> NoteTrigger {
+        NoteTrigger::new(&event(instrument, key), VANILLA_SOUNDS.len() as u8, batch)
+    }
+
+    #[test]
+    fn test_envelope_limits() {
+        let envelope = InstrumentEnvelope {
+            max_duration: Some(0.5),
+            release: -1.,
+        };
+        assert!(!envelope.is_expired(0.49));
+        assert!(envelope.is_expired(0.5));
+        assert_eq!(envelope.release_tween().duration, Duration::ZERO);
+
+        let default = InstrumentEnvelope::default();
+        assert!(!default.is_expired(f64::MAX));
+        assert_eq!(default.release_tween().duration, Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_custom_sounds_follow_vanilla_sounds() {
+        assert_eq!(sound_id(3, 16), 3);
+        assert_eq!(sound_id(16, 16), 16);
+        assert_eq!(sound_id(9, 10), 9);
+        assert_eq!(sound_id(10, 10), 16);
+        assert_eq!(sound_id(12, 10), 18);
+    }
+
+    #[test]
+    fn test_triggers_use_sound_ids() {
+        // The first custom instrument of a song saved with 10 vanilla instruments plays the
+        // first custom sound, whose envelope and cuts it shares
+        let custom = NoteTrigger::new(&event(10, 45), 10, 1);
+        assert_eq!(custom.sound_id, VANILLA_SOUNDS.len() as u32);
+        assert!(custom.is_cut_by(&NoteTrigger::new(&event(10, 45), 10, 2)));
+        assert!(!custom.is_cut_by(&note(10, 45, 2)));
+    }
+
+    #[test]
+    fn test_later_note_cuts_same_instrument_and_key() {
+        let ringing = note(0, 45, 3);
+
+        assert!(ringing.is_cut_by(&note(0, 45, 4)));
+        // Chords of the same tick ring together
+        assert!(!ringing.is_cut_by(&note(0, 45, 3)));
+        assert!(!ringing.is_cut_by(&note(0, 46, 4)));
+        assert!(!ringing.is_cut_by(&note(1, 45, 4)));
+    }
+
+    #[test]
+    fn test_notes_of_a_tick_share_a_batch() {
+        let mut batches = TriggerBatches::default();
+        let chord = batches.next(12);
+        assert_eq!(batches.next(12), chord);
+
+        // A song started again or sought back plays earlier ticks in later batches
+        let next = batches.next(0);
+        assert!(next > chord);
+        batches.end_batch();
+        assert!(batches.next(0) > next);
+    }
+}
@@ -0,0 +1,466 @@
+use std::path::{Path, PathBuf};
+
+use macroquad::{
+    color::{self, Color},
+    input::{
+        KeyCode, MouseButton, clear_input_queue, get_char_pressed, is_key_pressed,
+        is_mouse_button_pressed, mouse_position, mouse_wheel,
+    },
+    shapes::draw_rectangle,
+    text::{Font, TextParams, draw_text_ex, measure_text},
+};
+
+use crate::{
+    library::{self, Library},
+    playlist,
+    utils::time_formatter,
+};
+
+/// Songs read per frame while scanning, so the window keeps responding
+const SCAN_BATCH: usize = 8;
+const FONT_SIZE: u16 = 20;
+const ROW_HEIGHT: f32 = 26.0;
+const PADDING: f32 = 20.0;
+/// Rows moved by Page Up and Page Down
+const PAGE_ROWS: usize = 10;
+
+/// A song of the browsed folder, `None` details until it has been read
+struct SongEntry {
+    path: PathBuf,
+    details: Option<Result<SongDetails, String>>,
+}
+
+struct SongDetails {
+    title: String,
+    author: String,
+    duration: f32,
+}
+
+impl SongEntry {
+    fn file_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Whether every word of the lowercase `query` is in the title, author or file name
+    fn matches(&self, query: &str) -> bool {
+        let mut text = self.file_name().to_lowercase();
+        if let Some(Ok(details)) = &self.details {
+            text = format!(
+                "{} {} {}",
+                text,
+                details.title.to_lowercase(),
+                details.author.to_lowercase()
+            );
+        }
+        query.split_whitespace().all(|word| text.contains(word))
+    }
+}
+
+/// Details of a song from the library, indexing it first when it is new or changed
+fn read_details(library: &mut Library, path: &Path) -> Result<SongDetails, String> {
+    library.update_file(path)?;
+    let entry = library.entry(path).ok_or("Song missing from the library")?;
+    Ok(SongDetails {
+        title: entry.title.clone(),
+        author: entry.credits().unwrap_or_default(),
+        duration: entry.stats.duration_seconds,
+    })
+}
+
+/// What the player should do after a frame of the browser
+pub enum BrowserAction {
+    Browsing,
+    Close,
+    /// Play the listed songs, starting with the song at `index`
+    Play {
+        songs: Vec<PathBuf>,
+        index: usize,
+    },
+}
+
+/// Searchable list of the songs in a folder
+pub struct SongBrowser {
+    dir: PathBuf,
+    /// Cached song details, saved once the folder has been read
+    library: Library,
+    entries: Vec<SongEntry>,
+    /// Number of entries read so far
+    scanned: usize,
+    error: Option<String>,
+    query: String,
+    /// Indices into `entries` matching the query
+    visible: Vec<usize>,
+    selected: usize,
+    scroll: usize,
+}
+
+impl SongBrowser {
+    /// Opens the browser on a folder, dropping the keys typed before it opened
+    pub fn new(dir: PathBuf) -> Self {
+        clear_input_queue();
+        let mut browser = Self {
+            dir,
+            library: Library::load_default(),
+            entries: Vec::new(),
+            scanned: 0,
+            error: None,
+            query: String::new(),
+            visible: Vec::new(),
+            selected: 0,
+            scroll: 0,
+        };
+        browser.rescan();
+        browser
+    }
+
+    /// Lists the folder again, the songs being read over the next frames
+    pub fn rescan(&mut self) {
+        let (paths, error) = match playlist::songs_in_dir(&self.dir, &library::INDEXED_EXTENSIONS) {
+            Ok(paths) => (paths, None),
+            Err(error) => (Vec::new(), Some(error)),
+        };
+        self.entries = paths
+            .into_iter()
+            .map(|path| SongEntry {
+                path,
+                details: None,
+            })
+            .collect();
+        self.error = error;
+        self.scanned = 0;
+        self.filter();
+    }
+
+    fn is_scanning(&self) -> bool {
+        self.scanned < self.entries.len()
+    }
+
+    /// Reads the next few songs of the folder
+    fn scan_batch(&mut self) {
+        let end = (self.scanned + SCAN_BATCH).min(self.entries.len());
+        for entry in &mut self.entries[self.scanned..end] {
+            entry.details = Some(read_details(&mut self.library, &entry.path));
+        }
+        self.scanned = end;
+        self.filter();
+
+        if !self.is_scanning() {
+            self.library.forget_missing(&self.dir);
+            if let Err(error) = self.library.save_default() {
+                log::warn!("Failed to save the library: {}", error);
+            }
+        }
+    }
+
+    fn filter(&mut self) {
+        let selected_entry = self.visible.get(self.selected).copied();
+        let query = self.query.to_lowercase();
+        self.visible = (0..self.entries.len())
+            .filter(|&index| self.entries[index].matches(&query))
+            .collect();
+        self.selected = selected_entry
+            .and_then(|entry| self.visible.iter().position(|&index| index == entry))
+            .unwrap_or(0);
+    }
+
+    fn move_selection(&mut self, rows: isize) {
+        if self.visible.is_empty() {
+            return;
+        }
+        self.selected = self
+            .selected
+            .saturating_add_signed(rows)
+            .min(self.visible.len() - 1);
+    }
+
+    fn play_selected(&self) -> BrowserAction {
+        match self.visible.get(self.selected) {
+            Some(_) => BrowserAction::Play {
+                songs: self
+                    .visible
+                    .iter()
+                    .map(|&index| self.entries[index].path.clone())
+                    .collect(),
+                index: self.selected,
+            },
+            None => BrowserAction::Browsing,
+        }
+    }
+
+    /// Handles the frame's input, typed characters going to the search
+    pub fn update(&mut self, window_height: f32) -> BrowserAction {
+        if self.is_scanning() {
+            self.scan_batch();
+        }
+
+        // The queue pops the last typed character first
+        let mut typed = Vec::new();
+        while let Some(character) = get_char_pressed() {
+            typed.push(character);
+        }
+        let mut query_changed = false;
+        for character in typed.into_iter().rev() {
+            if !character.is_control() {
+                self.query.push(character);
+                query_changed = true;
+            }
+        }
+        if is_key_pressed(KeyCode::Backspace) {
+            query_changed |= self.query.pop().is_some();
+        }
+        if query_changed {
+            self.filter();
+            self.selected = 0;
+        }
+
+        if is_key_pressed(KeyCode::Escape) {
+            if self.query.is_empty() {
+                return BrowserAction::Close;
+            }
+            self.query.clear();
+            self.filter();
+        }
+        if is_key_pressed(KeyCode::F5) {
+            self.rescan();
+        }
+
+        if is_key_pressed(KeyCode::Down) {
+            self.move_selection(1);
+        }
+        if is_key_pressed(KeyCode::Up) {
+            self.move_selection(-1);
+        }
+        if is_key_pressed(KeyCode::PageDown) {
+            self.move_selection(PAGE_ROWS as isize);
+        }
+        if is_key_pressed(KeyCode::PageUp) {
+            self.move_selection(-(PAGE_ROWS as isize));
+        }
+        let (_, wheel) = mouse_wheel();
+        if wheel != 0. {
+            self.move_selection(if wheel < 0. { 3 } else { -3 });
+        }
+
+        let rows = Self::visible_rows(window_height);
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + rows {
+            self.scroll = self.selected + 1 - rows;
+        }
+
+        if is_mouse_button_pressed(MouseButton::Left) {
+            let (_, mouse_y) = mouse_position();
+            let row = ((mouse_y - Self::list_top()) / ROW_HEIGHT).floor();
+            if row >= 0.
+                && (row as usize) < rows
+                && self.scroll + (row as usize) < self.visible.len()
+            {
+                self.selected = self.scroll + row as usize;
+                return self.play_selected();
+            }
+        }
+
+        if is_key_pressed(KeyCode::Enter) || is_key_pressed(KeyCode::KpEnter) {
+            return self.play_selected();
+        }
+
+        BrowserAction::Browsing
+    }
+
+    fn list_top() -> f32 {
+        PADDING + 3. * ROW_HEIGHT
+    }
+
+    fn visible_rows(window_height: f32) -> usize {
+        (((window_height - Self::list_top() - PADDING - ROW_HEIGHT) / ROW_HEIGHT).max(1.)) as usize
+    }
+
+    pub fn draw(&self, window_width: f32, window_height: f32, font: &Font) {
+        let text_parameters = TextParams {
+            font_size: FONT_SIZE,
+            font: Some(font),
+            color: color::BLACK,
+            ..Default::default()
+        };
+        let measure = |text: &str| measure_text(text, Some(font), FONT_SIZE, 1.0).width;
+        let x = PADDING;
+        let mut y = PADDING + ROW_HEIGHT;
+
+        draw_text_ex(
+            &format!("Songs in {}", self.dir.display()),
+            x,
+            y,
+            TextParams {
+                color: color::RED,
+                ..text_parameters.clone()
+            },
+        );
+        let status = if self.is_scanning() {
+            format!("reading {}/{}", self.scanned, self.entries.len())
+        } else {
+            format!("{}/{} songs", self.visible.len(), self.entries.len())
+        };
+        draw_text_ex(
+            &status,
+            window_width - PADDING - measure(&status),
+            y,
+            text_parameters.clone(),
+        );
+
+        y += ROW_HEIGHT;
+        draw_text_ex(
+            &format!("Search: {}_", self.query),
+            x,
+            y,
+            text_parameters.clone(),
+        );
+
+        if let Some(error) = &self.error {
+            draw_text_ex(
+                error,
+                x,
+                Self::list_top() + ROW_HEIGHT,
+                TextParams {
+                    color: color::RED,
+                    ..text_parameters.clone()
+                },
+            );
+        }
+
+        let duration_x = window_width - PADDING - measure("00:00:00");
+        let author_x = window_width * 0.55;
+        let rows = Self::visible_rows(window_height);
+        for (row, &index) in self.visible.iter().skip(self.scroll).take(rows).enumerate() {
+            let entry = &self.entries[index];
+            let row_top = Self::list_top() + row as f32 * ROW_HEIGHT;
+            if self.scroll + row == self.selected {
+                draw_rectangle(
+                    0.,
+                    row_top,
+                    window_width,
+                    ROW_HEIGHT,
+                    Color::new(1., 1., 1., 0.6),
+                );
+            }
+
+            let baseline = row_top + ROW_HEIGHT * 0.75;
+            let (title, author, duration) = match &entry.details {
+                Some(Ok(details)) => (
+                    details.title.clone(),
+                    details.author.clone(),
+                    time_formatter(details.duration),
+                ),
+                Some(Err(error)) => (entry.file_name(), error.clone(), String::new()),
+                None => (entry.file_name(), "...".to_string(), String::new()),
+            };
+            draw_text_ex(&title, x, baseline, text_parameters.clone());
+            draw_text_ex(&author, author_x, baseline, text_parameters.clone());
+            draw_text_ex(&duration, duration_x, baseline, text_parameters.clone());
+        }
+
+        draw_text_ex(
+            "Type to search, Up/Down to choose, Enter to play, F5 to rescan, Esc to close",
+            x,
+            window_height - PADDING,
+            TextParams {
+                color: color::DARKGRAY,
+                ..text_parameters.clone()
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(file: &str, title: &str, author: &str) -> SongEntry {
+        SongEntry {
+            path: PathBuf::from(file),
+            details: Some(Ok(SongDetails {
+                title: title.to_string(),
+                author: author.to_string(),
+                duration: 60.,
+            })),
+        }
+    }
+
+    #[test]
+    fn test_entry_matches_every_word() {
+        let song = entry("songs/my_time.zip", "My Time", "bo en");
+
+        assert!(song.matches(""));
+        assert!(song.matches("time bo"));
+        assert!(song.matches("my_time"));
+        assert!(!song.matches("time nyan"));
+    }
+
+    fn browser(entries: Vec<SongEntry>) -> SongBrowser {
+        let mut browser = SongBrowser {
+            dir: PathBuf::from("songs"),
+            library: Library::default(),
+            scanned: entries.len(),
+            entries,
+            error: None,
+            query: String::new(),
+            visible: Vec::new(),
+            selected: 0,
+            scroll: 0,
+        };
+        browser.filter();
+        browser
+    }
+
+    fn played(action: BrowserAction) -> Option<(Vec<PathBuf>, usize)> {
+        match action {
+            BrowserAction::Play { songs, index } => Some((songs, index)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_selection_follows_the_filter() {
+        let mut browser = browser(vec![
+            entry("my_time.zip", "My Time", "bo en"),
+            entry("nyan_cat.nbs", "Nyan Cat", ""),
+            entry("time_machine.nbs", "Time Machine", ""),
+        ]);
+        assert_eq!(browser.visible, [0, 1, 2]);
+
+        // The selected song isn't listed anymore, so the first match is selected
+        browser.move_selection(1);
+        browser.query = "time".to_string();
+        browser.filter();
+        assert_eq!(browser.visible, [0, 2]);
+        assert_eq!(browser.selected, 0);
+
+        // Only the listed songs are played, from the selected one
+        browser.move_selection(PAGE_ROWS as isize);
+        assert_eq!(browser.selected, 1);
+        assert_eq!(
+            played(browser.play_selected()),
+            Some((
+                vec![
+                    PathBuf::from("my_time.zip"),
+                    PathBuf::from("time_machine.nbs")
+                ],
+                1
+            ))
+        );
+
+        // Clearing the search keeps the same song selected
+        browser.query.clear();
+        browser.filter();
+        assert_eq!(browser.selected, 2);
+        browser.move_selection(-(PAGE_ROWS as isize));
+        assert_eq!(browser.selected, 0);
+
+        browser.query = "nothing".to_string();
+        browser.filter();
+        browser.move_selection(1);
+        assert_eq!(played(browser.play_selected()), None);
+    }
+}
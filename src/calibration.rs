@@ -0,0 +1,217 @@
+use macroquad::{
+    color,
+    input::{KeyCode, MouseButton, is_key_pressed, is_mouse_button_pressed},
+    shapes::draw_circle,
+    text::{Font, TextParams, draw_text_ex},
+};
+
+use crate::{
+    audio::AudioEngine,
+    sink::{NoteEvent, NoteSink},
+};
+
+/// Time between two calibration clicks, in seconds (120 BPM)
+const CLICK_INTERVAL: f32 = 0.5;
+/// Number of clicks played in one calibration run
+const CLICK_COUNT: usize = 16;
+/// Silence before the first click, so the first tap is not rushed
+const LEAD_IN: f32 = 1.0;
+/// Taps further than this from any click are ignored, in seconds
+const MAX_TAP_OFFSET: f32 = CLICK_INTERVAL / 2.0;
+
+/// Converts a latency offset in milliseconds to a tick offset for the given tempo
+pub fn latency_ticks(latency_ms: f32, notes_per_second: f32) -> f32 {
+    latency_ms / 1000.0 * notes_per_second
+}
+
+/// Result of a frame of the calibration screen
+pub enum CalibrationState {
+    Running,
+    Cancelled,
+    /// Measured latency in milliseconds
+    Finished(f32),
+}
+
+/// Tap-along calibration: plays a steady click and measures how late the user taps
+#[derive(Default)]
+pub struct Calibration {
+    elapsed: f32,
+    clicks_played: usize,
+    tap_offsets: Vec<f32>,
+}
+
+impl Calibration {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn click_time(index: usize) -> f32 {
+        LEAD_IN + index as f32 * CLICK_INTERVAL
+    }
+
+    /// Median of the recorded tap offsets in milliseconds, if any tap was recorded
+    pub fn measured_latency(&self) -> Option<f32> {
+        if self.tap_offsets.is_empty() {
+            return None;
+        }
+
+        let mut offsets = self.tap_offsets.clone();
+        offsets.sort_by(|a, b| a.total_cmp(b));
+        let mid = offsets.len() / 2;
+        let median = if offsets.len().is_multiple_of(2) {
+            (offsets[mid - 1] + offsets[mid]) / 2.0
+        } else {
+            offsets[mid]
+        };
+
+        Some(median * 1000.0)
+    }
+
+    fn record_tap(&mut self) {
+        let nearest_click = ((self.elapsed - LEAD_IN) / CLICK_INTERVAL).round();
+        if nearest_click < 0.0 || nearest_click as usize >= CLICK_COUNT {
+            return;
+        }
+
+        let offset = self.elapsed - Self::click_time(nearest_click as usize);
+        if offset.abs() <= MAX_TAP_OFFSET {
+            self.tap_offsets.push(offset);
+        }
+    }
+
+    pub fn update(&mut self, audio_engine: &mut AudioEngine, delta_time: f32) -> CalibrationState {
+        if is_key_pressed(KeyCode::Escape) {
+            return CalibrationState::Cancelled;
+        }
+
+        self.elapsed += delta_time;
+
+        // Play every click whose time has come
+        while self.clicks_played < CLICK_COUNT
+            && self.elapsed >= Self::click_time(self.clicks_played)
+        {
+            audio_engine.trigger_note(&NoteEvent {
+                tick: self.clicks_played,
+                time: Self::click_time(self.clicks_played) as f64,
+                instrument: 4, // hat
+                key: 45,
+                velocity: 100,
+                panning: 100,
+                pitch: 0,
+            });
+            self.clicks_played += 1;
+        }
+
+        if is_key_pressed(KeyCode::Space) || is_mouse_button_pressed(MouseButton::Left) {
+            self.record_tap();
+        }
+
+        let last_click = Self::click_time(CLICK_COUNT - 1);
+        if self.elapsed > last_click + MAX_TAP_OFFSET {
+            return match self.measured_latency() {
+                Some(latency) => CalibrationState::Finished(latency),
+                None => CalibrationState::Cancelled,
+            };
+        }
+
+        CalibrationState::Running
+    }
+
+    pub fn draw(&self, window_width: f32, window_height: f32, font: &Font) {
+        let text_parameters = TextParams {
+            font_size: 20,
+            font: Some(font),
+            color: color::BLACK,
+            ..Default::default()
+        };
+
+        let start_x = window_width / 2. - 200.;
+        let start_y = window_height / 4.;
+
+        draw_text_ex(
+            "Latency Calibration",
+            start_x,
+            start_y,
+            TextParams {
+                font_size: 40,
+                color: color::RED,
+                ..text_parameters.clone()
+            },
+        );
+        draw_text_ex(
+            "Tap Space or click along with the clicks you hear",
+            start_x,
+            start_y + 40.,
+            text_parameters.clone(),
+        );
+        draw_text_ex(
+            &format!(
+                "Click {}/{} - Taps recorded: {}",
+                self.clicks_played,
+                CLICK_COUNT,
+                self.tap_offsets.len()
+            ),
+            start_x,
+            start_y + 70.,
+            text_parameters.clone(),
+        );
+
+        if let Some(latency) = self.measured_latency() {
+            draw_text_ex(
+                &format!("Measured latency: {:+.0} ms", latency),
+                start_x,
+                start_y + 100.,
+                text_parameters.clone(),
+            );
+        }
+
+        draw_text_ex(
+            "Press Escape to cancel",
+            start_x,
+            start_y + 130.,
+            text_parameters.clone(),
+        );
+
+        // Flash a dot on the scheduled click, useful to compare against what is heard
+        let since_click = self.elapsed - LEAD_IN;
+        if self.clicks_played > 0 && since_click >= 0.0 && since_click % CLICK_INTERVAL < 0.08 {
+            draw_circle(window_width / 2., window_height / 2. + 40., 20., color::RED);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_ticks() {
+        assert_eq!(latency_ticks(1000.0, 10.0), 10.0);
+        assert_eq!(latency_ticks(-50.0, 20.0), -1.0);
+    }
+
+    #[test]
+    fn test_measured_latency_median() {
+        let mut calibration = Calibration::new();
+        assert!(calibration.measured_latency().is_none());
+
+        calibration.tap_offsets = vec![0.05, 0.04, 0.2, 0.06];
+        assert!((calibration.measured_latency().unwrap() - 55.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_record_tap_nearest_click() {
+        let mut calibration = Calibration::new();
+
+        // 30 ms after the third click
+        calibration.elapsed = Calibration::click_time(2) + 0.03;
+        calibration.record_tap();
+
+        // Before the lead-in is over, ignored
+        calibration.elapsed = 0.1;
+        calibration.record_tap();
+
+        assert_eq!(calibration.tap_offsets.len(), 1);
+        assert!((calibration.tap_offsets[0] - 0.03).abs() < 1e-4);
+    }
+}
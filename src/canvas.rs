@@ -0,0 +1,331 @@
+use macroquad::{
+    color::Color,
+    math::Vec2,
+    shapes::draw_rectangle,
+    text::{Font, TextDimensions, TextParams, draw_text_ex, measure_text},
+    texture::{DrawTextureParams, FilterMode, Image, Texture2D, draw_texture_ex},
+};
+
+/// Textures of the falling-notes view
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sprite {
+    NoteBlock,
+    WhiteKey,
+    BlackKey,
+}
+
+impl Sprite {
+    pub const ALL: [Sprite; 3] = [Sprite::NoteBlock, Sprite::WhiteKey, Sprite::BlackKey];
+
+    pub fn png_bytes(self) -> &'static [u8] {
+        match self {
+            Sprite::NoteBlock => include_bytes!("../assets/textures/note_block.png"),
+            Sprite::WhiteKey => include_bytes!("../assets/textures/key_white.png"),
+            Sprite::BlackKey => include_bytes!("../assets/textures/key_black.png"),
+        }
+    }
+}
+
+/// Drawing operations of the falling-notes view, so it can be drawn on the window or into an
+/// image without a GPU
+pub trait Canvas {
+    fn draw_rectangle(&mut self, x: f32, y: f32, width: f32, height: f32, color: Color);
+
+    /// Draws a sprite stretched over a rectangle, multiplied by `tint`
+    fn draw_sprite(&mut self, sprite: Sprite, x: f32, y: f32, width: f32, height: f32, tint: Color);
+
+    fn measure_text(&self, text: &str, font_size: u16) -> TextDimensions;
+
+    /// Draws text with its baseline at `y`
+    fn draw_text(&mut self, text: &str, x: f32, y: f32, font_size: u16, color: Color);
+}
+
+/// Draws on the window with macroquad
+pub struct ScreenCanvas {
+    textures: [Texture2D; 3],
+    font: Font,
+}
+
+impl ScreenCanvas {
+    /// Uploads the sprites, which needs the window to be open
+    pub fn new(font: Font) -> Self {
+        let textures = Sprite::ALL.map(|sprite| {
+            let texture = Texture2D::from_file_with_format(sprite.png_bytes(), None);
+            texture.set_filter(FilterMode::Nearest);
+            texture
+        });
+        Self { textures, font }
+    }
+}
+
+impl Canvas for ScreenCanvas {
+    fn draw_rectangle(&mut self, x: f32, y: f32, width: f32, height: f32, color: Color) {
+        draw_rectangle(x, y, width, height, color);
+    }
+
+    fn draw_sprite(
+        &mut self,
+        sprite: Sprite,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        tint: Color,
+    ) {
+        draw_texture_ex(
+            &self.textures[sprite as usize],
+            x,
+            y,
+            tint,
+            DrawTextureParams {
+                dest_size: Some(Vec2::new(width, height)),
+                ..Default::default()
+            },
+        );
+    }
+
+    fn measure_text(&self, text: &str, font_size: u16) -> TextDimensions {
+        measure_text(text, Some(&self.font), font_size, 1.0)
+    }
+
+    fn draw_text(&mut self, text: &str, x: f32, y: f32, font_size: u16, color: Color) {
+        draw_text_ex(
+            text,
+            x,
+            y,
+            TextParams {
+                font: Some(&self.font),
+                font_size,
+                color,
+                ..Default::default()
+            },
+        );
+    }
+}
+
+/// Software renderer into an RGBA image, for rendering tests on machines without a GPU.
+/// Sprites are sampled nearest-neighbour like on screen. Text is measured with a fixed
+/// advance and not drawn, since rasterizing the font needs a GPU context.
+pub struct ImageCanvas {
+    image: Image,
+    sprites: [Image; 3],
+}
+
+impl ImageCanvas {
+    pub fn new(width: u16, height: u16, background: Color) -> Self {
+        let sprites = Sprite::ALL.map(|sprite| {
+            Image::from_file_with_format(sprite.png_bytes(), None)
+                .expect("Sprites are valid PNG files")
+        });
+        Self {
+            image: Image::gen_image_color(width, height, background),
+            sprites,
+        }
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    /// Pixel columns or rows whose centers are covered by a span, clamped to `size`
+    fn pixel_span(start: f32, length: f32, size: u16) -> std::ops::Range<u32> {
+        let first = (start - 0.5).ceil().max(0.) as u32;
+        let end = (start + length - 0.5).ceil().clamp(0., size as f32) as u32;
+        first.min(end)..end
+    }
+
+    /// Alpha-blends a color over a pixel
+    fn blend(&mut self, x: u32, y: u32, color: Color) {
+        let below = self.image.get_pixel(x, y);
+        let alpha = color.a;
+        self.image.set_pixel(
+            x,
+            y,
+            Color::new(
+                color.r * alpha + below.r * (1. - alpha),
+                color.g * alpha + below.g * (1. - alpha),
+                color.b * alpha + below.b * (1. - alpha),
+                alpha + below.a * (1. - alpha),
+            ),
+        );
+    }
+}
+
+impl Canvas for ImageCanvas {
+    fn draw_rectangle(&mut self, x: f32, y: f32, width: f32, height: f32, color: Color) {
+        for py in Self::pixel_span(y, height, self.image.height) {
+            for px in Self::pixel_span(x, width, self.image.width) {
+                self.blend(px, py, color);
+            }
+        }
+    }
+
+    fn draw_sprite(
+        &mut self,
+        sprite: Sprite,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        tint: Color,
+    ) {
+        let (sprite_width, sprite_height) = {
+            let sprite = &self.sprites[sprite as usize];
+            (sprite.width as f32, sprite.height as f32)
+        };
+        for py in Self::pixel_span(y, height, self.image.height) {
+            let v = ((py as f32 + 0.5 - y) / height * sprite_height).min(sprite_height - 1.);
+            for px in Self::pixel_span(x, width, self.image.width) {
+                let u = ((px as f32 + 0.5 - x) / width * sprite_width).min(sprite_width - 1.);
+                let texel = self.sprites[sprite as usize].get_pixel(u as u32, v as u32);
+                let color = Color::new(
+                    texel.r * tint.r,
+                    texel.g * tint.g,
+                    texel.b * tint.b,
+                    texel.a * tint.a,
+                );
+                self.blend(px, py, color);
+            }
+        }
+    }
+
+    fn measure_text(&self, text: &str, font_size: u16) -> TextDimensions {
+        let height = font_size as f32 * 0.7;
+        TextDimensions {
+            width: text.chars().count() as f32 * font_size as f32 * 0.6,
+            height,
+            offset_y: height,
+        }
+    }
+
+    fn draw_text(&mut self, _text: &str, _x: f32, _y: f32, _font_size: u16, _color: Color) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use macroquad::color;
+
+    use super::*;
+    use crate::{note, piano};
+
+    /// Window sizes and ticks of nyan_cat.nbs covered by the golden images
+    const WINDOW_SIZES: [(u16, u16); 3] = [(640, 360), (1280, 720), (1024, 768)];
+    const TICKS: [f32; 3] = [0., 36.5, 150.];
+
+    fn golden_path(width: u16, height: u16, tick: f32) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("test-assets/golden")
+            .join(format!("nyan_cat_{}x{}_tick{}.png", width, height, tick))
+    }
+
+    /// Writes an image as PNG. `Image::export_png` flips the rows of GPU read-backs, so the
+    /// rows are flipped here first.
+    fn save_png(image: &Image, path: &std::path::Path) {
+        let row = image.width as usize * 4;
+        let bytes = image.bytes.chunks(row).rev().flatten().copied().collect();
+        let flipped = Image {
+            bytes,
+            width: image.width,
+            height: image.height,
+        };
+        flipped.export_png(path.to_str().unwrap());
+    }
+
+    /// Draws the view the way the player does, without key presses
+    fn render(width: u16, height: u16, current_tick: f32) -> Image {
+        let song =
+            crate::song::load_nbs_file(Some(include_bytes!("../test-assets/nyan_cat.nbs"))).song;
+        let note_blocks = note::get_note_blocks(&song);
+        let (all_keys, key_map) = piano::generate_piano_keys();
+        let piano_props = piano::initialize_piano_dimensions(width as f32, &all_keys);
+
+        let mut canvas = ImageCanvas::new(width, height, color::SKYBLUE);
+        note::draw_notes(
+            &mut canvas,
+            width as f32,
+            height as f32,
+            &all_keys,
+            &key_map,
+            &note_blocks,
+            &piano_props,
+            current_tick,
+            piano_props.white_key_width,
+            piano_props.key_spacing,
+            &note::generate_instrument_palette(),
+        );
+        piano::draw_piano_keys(
+            &mut canvas,
+            width as f32,
+            height as f32,
+            &all_keys,
+            &piano_props,
+        );
+        canvas.image
+    }
+
+    #[test]
+    fn test_rectangles_cover_pixel_centers() {
+        let mut canvas = ImageCanvas::new(4, 4, color::BLACK);
+        canvas.draw_rectangle(0.6, 1., 2.5, 10., color::WHITE);
+
+        let white: Vec<(u32, u32)> = (0..4)
+            .flat_map(|y| (0..4).map(move |x| (x, y)))
+            .filter(|&(x, y)| canvas.image().get_pixel(x, y) == color::WHITE)
+            .collect();
+        assert_eq!(white, vec![(1, 1), (2, 1), (1, 2), (2, 2), (1, 3), (2, 3)]);
+    }
+
+    /// Compares renders with the images in test-assets/golden. Run with `UPDATE_GOLDEN=1` to
+    /// write them again after an intended layout change.
+    #[test]
+    fn test_falling_notes_match_golden_images() {
+        let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+        let mut mismatches = Vec::new();
+
+        for (width, height) in WINDOW_SIZES {
+            for tick in TICKS {
+                let path = golden_path(width, height, tick);
+                let image = render(width, height, tick);
+                if update {
+                    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                    save_png(&image, &path);
+                    continue;
+                }
+
+                let golden = std::fs::read(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|bytes| {
+                        Image::from_file_with_format(&bytes, None).map_err(|e| e.to_string())
+                    })
+                    .unwrap_or_else(|e| {
+                        panic!(
+                            "Can't read {}: {}, run with UPDATE_GOLDEN=1 to create it",
+                            path.display(),
+                            e
+                        )
+                    });
+
+                let matches = golden.width == image.width
+                    && golden.height == image.height
+                    && golden
+                        .bytes
+                        .iter()
+                        .zip(&image.bytes)
+                        .all(|(a, b)| a.abs_diff(*b) <= 1);
+                if !matches {
+                    let actual = std::env::temp_dir().join(path.file_name().unwrap());
+                    save_png(&image, &actual);
+                    mismatches.push(format!("{} (got {})", path.display(), actual.display()));
+                }
+            }
+        }
+
+        assert!(
+            mismatches.is_empty(),
+            "Renders differ from the golden images:\n{}",
+            mismatches.join("\n")
+        );
+    }
+}
@@ -0,0 +1,622 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use crate::{
+    datapack, info, library, lint, midi, minecraft, pack, playlist, schematic, song,
+    utils::time_formatter, writer,
+};
+
+pub const USAGE: &str = "\
+usage: nbs-player-rs [play] [--shuffle] [--repeat <mode>] [--browse <dir>] [--library <words>]
+                     [--tps <ticks>] [<song>...]
+                                                      open the player
+       nbs-player-rs info [--json] [--tps <ticks>] <song>
+                                                      show the song's metadata and statistics
+       nbs-player-rs check [--strict] [--tps <ticks>] <song>...
+                                                      report problems in songs
+       nbs-player-rs library scan <dir>...          index the .nbs and .zip songs of folders
+       nbs-player-rs library search [--instrument <name>] [--min <seconds>] [--max <seconds>]
+                            [--json | --m3u] [<words>...]
+                                                      list indexed songs, every filter matching
+       nbs-player-rs export --midi <out.mid> [--programs <table>] <song>
+       nbs-player-rs export --nbs <out.nbs> [--nbs-version <0-5>] <song>
+       nbs-player-rs export --pack <out.zip> [--sounds <dir>]... <song>
+       nbs-player-rs export --datapack <out.zip> [--mc-version <1.x>] [--namespace <name>] <song>
+       nbs-player-rs export --schem <out.schem> [--mc-version <1.x>] <song>
+
+songs can be .nbs files, ZIP song packs or .mid files
+
+play options, songs can also be folders of songs and .m3u playlists:
+    --shuffle             play the songs in random order
+    --repeat <mode>       off, all to loop the playlist or one to loop each song
+    --browse <dir>        start in the song browser, listing the .nbs and .zip files of <dir>
+    --library <words>     also play the indexed songs whose title, authors or file name contain
+                          <words>, an empty string playing the whole library
+    keys: N next song, P previous song, S shuffle, R repeat mode, B song browser,
+    left and right arrows to seek,
+    songs dropped on the window replace the playlist
+
+info options:
+    --json                print the info as JSON
+
+library options, the index is kept in the user data folder:
+    --instrument <name>   songs using an instrument whose name contains <name>
+    --min <seconds>       songs lasting at least <seconds>
+    --max <seconds>       songs lasting at most <seconds>
+    --json                print the matching index entries as JSON
+    --m3u                 print the matching songs as a playlist for the player
+
+check options, check exits with 1 when a song has errors:
+    --strict              also exit with 1 when a song has warnings
+
+export options:
+    --midi <out.mid>      write a Standard MIDI File, one track per layer
+    --programs <table>    General MIDI program table, one `<instrument> <program>`
+                          or `<instrument> drum:<note>` per line
+    --nbs <out.nbs>       write an NBS file, e.g. to save a converted MIDI file
+    --nbs-version <0-5>   NBS version to write, defaults to the version of the song
+    --pack <out.zip>      write a song pack with every custom instrument sound
+    --sounds <dir>        folder to look for custom sounds in, can be repeated, the
+                          song's folder and its sounds subfolder are always searched
+    --datapack <out.zip>  write a Minecraft data pack playing the song with /playsound, start
+                          it with /function <namespace>:play and stop it with <namespace>:stop
+    --schem <out.schem>   write a Sponge schematic of note blocks and repeaters, one lane per
+                          layer, started by the button at its origin
+    --mc-version <1.x>    Minecraft version the data pack or schematic targets, 1.13 to 1.21
+                          (default 1.21)
+    --namespace <name>    data pack namespace, defaults to the song name
+    --tps <ticks>         ticks per second used when importing a .mid song (default 20)";
+
+pub enum ExportFormat {
+    Midi { programs: Option<PathBuf> },
+    Nbs { version: Option<u8> },
+    Pack { sound_dirs: Vec<PathBuf> },
+    Datapack(datapack::DatapackOptions),
+    Schematic(schematic::SchematicOptions),
+}
+
+pub enum Command {
+    Play {
+        songs: Vec<PathBuf>,
+        options: song::LoadOptions,
+        shuffle: bool,
+        repeat: playlist::RepeatMode,
+        /// Folder to open the song browser on at start
+        browse: Option<PathBuf>,
+        /// Indexed songs to add to the playlist
+        library: Option<library::LibraryQuery>,
+    },
+    Help,
+    Info {
+        song: PathBuf,
+        options: song::LoadOptions,
+        json: bool,
+    },
+    Check {
+        songs: Vec<PathBuf>,
+        options: song::LoadOptions,
+        strict: bool,
+    },
+    LibraryScan {
+        dirs: Vec<PathBuf>,
+    },
+    LibrarySearch {
+        query: library::LibraryQuery,
+        json: bool,
+        m3u: bool,
+    },
+    Export {
+        song: PathBuf,
+        output: PathBuf,
+        format: ExportFormat,
+        options: song::LoadOptions,
+    },
+}
+
+/// Returns the value following a flag
+fn flag_value<'a>(
+    flag: &str,
+    args: &mut impl Iterator<Item = &'a String>,
+) -> Result<&'a String, String> {
+    args.next()
+        .ok_or_else(|| format!("Missing value for {}", flag))
+}
+
+fn parse_tps(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(tps) if tps > 0.0 => Ok(tps),
+        _ => Err(format!("Invalid ticks per second {}", value)),
+    }
+}
+
+fn parse_play(args: &[String]) -> Result<Command, String> {
+    let mut songs = Vec::new();
+    let mut options = song::LoadOptions::default();
+    let mut shuffle = false;
+    let mut repeat = playlist::RepeatMode::Off;
+    let mut browse = None;
+    let mut library = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--shuffle" => shuffle = true,
+            "--repeat" => {
+                let value = flag_value(arg, &mut args)?;
+                repeat = playlist::RepeatMode::parse(value)
+                    .ok_or_else(|| format!("Invalid repeat mode {}", value))?;
+            }
+            "--browse" => browse = Some(PathBuf::from(flag_value(arg, &mut args)?)),
+            "--library" => {
+                library = Some(library::LibraryQuery {
+                    text: flag_value(arg, &mut args)?.clone(),
+                    ..Default::default()
+                })
+            }
+            "--tps" => options.midi.ticks_per_second = parse_tps(flag_value(arg, &mut args)?)?,
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            path => songs.push(PathBuf::from(path)),
+        }
+    }
+
+    Ok(Command::Play {
+        songs,
+        options,
+        shuffle,
+        repeat,
+        browse,
+        library,
+    })
+}
+
+fn parse_info(args: &[String]) -> Result<Command, String> {
+    let mut song = None;
+    let mut options = song::LoadOptions::default();
+    let mut json = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--tps" => options.midi.ticks_per_second = parse_tps(flag_value(arg, &mut args)?)?,
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            path if song.is_none() => song = Some(PathBuf::from(path)),
+            extra => return Err(format!("Unexpected argument {}", extra)),
+        }
+    }
+
+    let song = song.ok_or("Missing song")?;
+    Ok(Command::Info {
+        song,
+        options,
+        json,
+    })
+}
+
+fn parse_check(args: &[String]) -> Result<Command, String> {
+    let mut songs = Vec::new();
+    let mut options = song::LoadOptions::default();
+    let mut strict = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--strict" => strict = true,
+            "--tps" => options.midi.ticks_per_second = parse_tps(flag_value(arg, &mut args)?)?,
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            path => songs.push(PathBuf::from(path)),
+        }
+    }
+
+    if songs.is_empty() {
+        return Err("Missing song".to_string());
+    }
+    Ok(Command::Check {
+        songs,
+        options,
+        strict,
+    })
+}
+
+fn parse_seconds(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(seconds) if seconds >= 0.0 => Ok(seconds),
+        _ => Err(format!("Invalid duration {}", value)),
+    }
+}
+
+fn parse_library(args: &[String]) -> Result<Command, String> {
+    match args.first().map(String::as_str) {
+        Some("scan") if args.len() > 1 => Ok(Command::LibraryScan {
+            dirs: args[1..].iter().map(PathBuf::from).collect(),
+        }),
+        Some("scan") => Err("Missing folder to scan".to_string()),
+        Some("search") => {
+            let mut query = library::LibraryQuery::default();
+            let mut words = Vec::new();
+            let mut json = false;
+            let mut m3u = false;
+
+            let mut args = args[1..].iter();
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--instrument" => query.instrument = Some(flag_value(arg, &mut args)?.clone()),
+                    "--min" => {
+                        query.min_duration = Some(parse_seconds(flag_value(arg, &mut args)?)?)
+                    }
+                    "--max" => {
+                        query.max_duration = Some(parse_seconds(flag_value(arg, &mut args)?)?)
+                    }
+                    "--json" => json = true,
+                    "--m3u" => m3u = true,
+                    flag if flag.starts_with("--") => {
+                        return Err(format!("Unknown option {}", flag));
+                    }
+                    word => words.push(word),
+                }
+            }
+            if json && m3u {
+                return Err("Choose either --json or --m3u".to_string());
+            }
+            query.text = words.join(" ");
+
+            Ok(Command::LibrarySearch { query, json, m3u })
+        }
+        Some(other) => Err(format!("Unknown library command {}", other)),
+        None => Err("Missing library command".to_string()),
+    }
+}
+
+fn parse_export(args: &[String]) -> Result<Command, String> {
+    let mut song = None;
+    let mut output = None;
+    let mut format = None;
+    let mut programs = None;
+    let mut nbs_version = None;
+    let mut sound_dirs = Vec::new();
+    let mut game_version = minecraft::GameVersion::LATEST;
+    let mut namespace = None;
+    let mut options = song::LoadOptions::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--midi" => {
+                output = Some(PathBuf::from(flag_value(arg, &mut args)?));
+                format = Some("midi");
+            }
+            "--nbs" => {
+                output = Some(PathBuf::from(flag_value(arg, &mut args)?));
+                format = Some("nbs");
+            }
+            "--nbs-version" => {
+                let value = flag_value(arg, &mut args)?;
+                nbs_version = match value.parse::<u8>() {
+                    Ok(version) if version <= writer::LATEST_VERSION => Some(version),
+                    _ => return Err(format!("Invalid NBS version {}", value)),
+                };
+            }
+            "--pack" => {
+                output = Some(PathBuf::from(flag_value(arg, &mut args)?));
+                format = Some("pack");
+            }
+            "--datapack" => {
+                output = Some(PathBuf::from(flag_value(arg, &mut args)?));
+                format = Some("datapack");
+            }
+            "--schem" => {
+                output = Some(PathBuf::from(flag_value(arg, &mut args)?));
+                format = Some("schem");
+            }
+            "--mc-version" => {
+                let value = flag_value(arg, &mut args)?;
+                game_version = minecraft::GameVersion::parse(value)
+                    .ok_or_else(|| format!("Unsupported Minecraft version {}", value))?;
+            }
+            "--namespace" => namespace = Some(flag_value(arg, &mut args)?.clone()),
+            "--sounds" => sound_dirs.push(PathBuf::from(flag_value(arg, &mut args)?)),
+            "--programs" => programs = Some(PathBuf::from(flag_value(arg, &mut args)?)),
+            "--tps" => options.midi.ticks_per_second = parse_tps(flag_value(arg, &mut args)?)?,
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            path if song.is_none() => song = Some(PathBuf::from(path)),
+            extra => return Err(format!("Unexpected argument {}", extra)),
+        }
+    }
+
+    let song = song.ok_or("Missing song to export")?;
+    let output = output.ok_or("Missing export format")?;
+    let format = match format {
+        Some("midi") => ExportFormat::Midi { programs },
+        Some("nbs") => ExportFormat::Nbs {
+            version: nbs_version,
+        },
+        Some("pack") => ExportFormat::Pack { sound_dirs },
+        Some("datapack") => ExportFormat::Datapack(datapack::DatapackOptions {
+            version: game_version,
+            namespace,
+        }),
+        Some("schem") => ExportFormat::Schematic(schematic::SchematicOptions {
+            version: game_version,
+        }),
+        _ => return Err("Missing export format".to_string()),
+    };
+
+    Ok(Command::Export {
+        song,
+        output,
+        format,
+        options,
+    })
+}
+
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    match args.first().map(String::as_str) {
+        Some("-h" | "--help" | "help") => Ok(Command::Help),
+        None => parse_play(args),
+        Some("play") => parse_play(&args[1..]),
+        Some("info") => parse_info(&args[1..]),
+        Some("check") => parse_check(&args[1..]),
+        Some("export") => parse_export(&args[1..]),
+        Some("library") => parse_library(&args[1..]),
+        // Songs given without a command are played
+        Some(other) if other.starts_with("--") || Path::new(other).exists() => parse_play(args),
+        Some(other) => Err(format!("Unknown command {}", other)),
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+}
+
+fn write_file(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    fs::write(path, bytes).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+fn info(song_path: &Path, options: &song::LoadOptions, json: bool) -> Result<(), String> {
+    let song_bytes = read_file(song_path)?;
+    let song_data = song::load_song_file(&song_bytes, song_path, options)
+        .map_err(|e| format!("{}: {}", song_path.display(), e))?;
+    let info = info::song_info(
+        song_path,
+        &song_bytes,
+        &song_data,
+        &library::Library::load_default(),
+    );
+
+    if json {
+        let text = serde_json::to_string_pretty(&info)
+            .map_err(|e| format!("Failed to serialize the song info: {}", e))?;
+        println!("{}", text);
+    } else {
+        info::print_info(&info);
+    }
+    Ok(())
+}
+
+/// Prints the problems of every song, failing when one has errors, or warnings in strict mode
+fn check(songs: &[PathBuf], options: &song::LoadOptions, strict: bool) -> Result<ExitCode, String> {
+    let mut errors = 0;
+    let mut warnings = 0;
+
+    for song_path in songs {
+        // A song that can't be loaded is reported like its other errors
+        let loaded = fs::read(song_path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| {
+                song::load_song_file(&bytes, song_path, options).map(|song_data| (bytes, song_data))
+            });
+        let (song_bytes, song_data) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                println!("{}: error: {}", song_path.display(), e);
+                errors += 1;
+                continue;
+            }
+        };
+        let samples = info::sample_statuses(song_path, &song_bytes, &song_data);
+
+        for issue in lint::check(&song_data.song, &samples) {
+            let severity = match issue.severity() {
+                lint::Severity::Error => {
+                    errors += 1;
+                    "error"
+                }
+                lint::Severity::Warning => {
+                    warnings += 1;
+                    "warning"
+                }
+            };
+            println!("{}: {}: {}", song_path.display(), severity, issue.message);
+        }
+    }
+
+    println!(
+        "{} errors, {} warnings in {} songs",
+        errors,
+        warnings,
+        songs.len()
+    );
+    if errors > 0 || (strict && warnings > 0) {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn export(
+    song_path: &Path,
+    output: &Path,
+    format: &ExportFormat,
+    options: &song::LoadOptions,
+) -> Result<(), String> {
+    let song_bytes = read_file(song_path)?;
+    let song_data = song::load_song_file(&song_bytes, song_path, options)
+        .map_err(|e| format!("{}: {}", song_path.display(), e))?;
+
+    let bytes = match format {
+        ExportFormat::Midi { programs } => {
+            let programs = match programs {
+                Some(path) => {
+                    let text = String::from_utf8_lossy(&read_file(path)?).into_owned();
+                    midi::GmProgramTable::parse(&text)?
+                }
+                None => midi::GmProgramTable::default(),
+            };
+            let (bytes, report) = midi::export_midi(&song_data.song, &programs);
+            for warning in report.warnings() {
+                eprintln!("Warning: {}", warning);
+            }
+            bytes
+        }
+        ExportFormat::Nbs { version } => match version {
+            Some(version) => writer::write_nbs_version(&song_data.song, *version),
+            None => writer::write_nbs(&song_data.song),
+        },
+        ExportFormat::Pack { sound_dirs } => {
+            let search_dirs = pack::sound_search_dirs(song_path, sound_dirs);
+            let sounds = pack::resolve_sounds(&song_data.song, &search_dirs)?;
+            pack::write_pack(&song_data.song, &sounds)?
+        }
+        ExportFormat::Datapack(datapack_options) => {
+            let (bytes, report) = datapack::export_datapack(&song_data.song, datapack_options)?;
+            for warning in report.warnings() {
+                eprintln!("Warning: {}", warning);
+            }
+            println!(
+                "Data pack plays {} notes, run /function {}:play to start",
+                report.notes, report.namespace
+            );
+            bytes
+        }
+        ExportFormat::Schematic(schematic_options) => {
+            let (bytes, report) = schematic::export_schematic(&song_data.song, schematic_options)?;
+            for warning in report.warnings() {
+                eprintln!("Warning: {}", warning);
+            }
+            let (width, height, length) = report.size;
+            println!(
+                "Schematic has {} notes in {} lanes, {}x{}x{} blocks, press the button at its \
+                 origin to play",
+                report.placed, report.lanes, width, height, length
+            );
+            bytes
+        }
+    };
+
+    write_file(output, &bytes)?;
+    println!("Exported {} to {}", song_path.display(), output.display());
+    Ok(())
+}
+
+/// Indexes folders into the library and saves it, even when some folders can't be read
+fn library_scan(dirs: &[PathBuf]) -> Result<(), String> {
+    let mut library = library::Library::load_default();
+    let mut failed_dirs = 0;
+
+    for dir in dirs {
+        let report = match library.refresh_dir(dir) {
+            Ok(report) => report,
+            Err(error) => {
+                eprintln!("{}: {}", dir.display(), error);
+                failed_dirs += 1;
+                continue;
+            }
+        };
+        for (path, error) in &report.failed {
+            eprintln!("{}: {}", path.display(), error);
+        }
+        println!(
+            "{}: {} added, {} updated, {} unchanged, {} removed, {} failed",
+            dir.display(),
+            report.added,
+            report.updated,
+            report.unchanged,
+            report.removed,
+            report.failed.len()
+        );
+    }
+
+    library.save_default()?;
+    if failed_dirs > 0 {
+        return Err(format!(
+            "{} of {} folders couldn't be scanned",
+            failed_dirs,
+            dirs.len()
+        ));
+    }
+    Ok(())
+}
+
+fn library_search(query: &library::LibraryQuery, json: bool, m3u: bool) -> Result<(), String> {
+    let library = library::Library::load_default();
+    let found = library.search(query);
+
+    if json {
+        let text = serde_json::to_string_pretty(&found)
+            .map_err(|e| format!("Failed to serialize the songs: {}", e))?;
+        println!("{}", text);
+    } else if m3u {
+        println!("#EXTM3U");
+        for entry in found {
+            println!(
+                "#EXTINF:{:.0},{}",
+                entry.stats.duration_seconds, entry.title
+            );
+            println!("{}", entry.path.display());
+        }
+    } else {
+        for entry in &found {
+            println!(
+                "{:<40} {:<30} {:>8}  {}",
+                entry.title,
+                entry.credits().unwrap_or_default(),
+                time_formatter(entry.stats.duration_seconds),
+                entry.path.display()
+            );
+        }
+        println!("{} of {} songs", found.len(), library.songs().len());
+    }
+    Ok(())
+}
+
+/// Runs a command that doesn't need the player window
+pub fn run(command: Command) -> ExitCode {
+    let result = match command {
+        Command::Play { .. } => Ok(ExitCode::SUCCESS),
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Info {
+            song,
+            options,
+            json,
+        } => info(&song, &options, json).map(|_| ExitCode::SUCCESS),
+        Command::Check {
+            songs,
+            options,
+            strict,
+        } => check(&songs, &options, strict),
+        Command::LibraryScan { dirs } => library_scan(&dirs).map(|_| ExitCode::SUCCESS),
+        Command::LibrarySearch { query, json, m3u } => {
+            library_search(&query, json, m3u).map(|_| ExitCode::SUCCESS)
+        }
+        Command::Export {
+            song,
+            output,
+            format,
+            options,
+        } => export(&song, &output, &format, &options).map(|_| ExitCode::SUCCESS),
+    };
+
+    match result {
+        Ok(code) => code,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
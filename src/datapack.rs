@@ -0,0 +1,394 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use nbs_rs::NbsFile;
+
+use crate::{
+    minecraft::{self, GAME_TICKS_PER_SECOND, GameVersion},
+    pack,
+    utils::decode_text,
+};
+
+/// Game ticks with notes handled by a single dispatch function
+const DISPATCH_LEAF_SIZE: usize = 8;
+/// Distance in blocks of a fully panned note from the listener
+const PAN_DISTANCE: f32 = 2.0;
+/// Scoreboard objective names are limited to 16 characters before 1.18
+const MAX_OBJECTIVE_LENGTH: usize = 16;
+
+pub struct DatapackOptions {
+    pub version: GameVersion,
+    /// Function namespace, defaults to the song name
+    pub namespace: Option<String>,
+}
+
+impl Default for DatapackOptions {
+    fn default() -> Self {
+        Self {
+            version: GameVersion::LATEST,
+            namespace: None,
+        }
+    }
+}
+
+/// What had to change for the song to play in game
+#[derive(Debug, Default)]
+pub struct DatapackReport {
+    pub namespace: String,
+    pub notes: usize,
+    /// Notes moved by octaves into the note block range
+    pub transposed: usize,
+    /// Notes with a custom instrument, which have no vanilla sound
+    pub skipped_custom: usize,
+    /// Notes played as harp because their instrument is newer than the target version
+    pub fallback_instrument: usize,
+    /// Song ticks that landed on the same game tick as an earlier one
+    pub merged_ticks: usize,
+    /// The song tempo doesn't divide the game tick rate, so timing is rounded
+    pub uneven_timing: bool,
+}
+
+impl DatapackReport {
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.transposed > 0 {
+            warnings.push(format!(
+                "{} notes were transposed into the note block range",
+                self.transposed
+            ));
+        }
+        if self.skipped_custom > 0 {
+            warnings.push(format!(
+                "{} notes with custom instruments were skipped",
+                self.skipped_custom
+            ));
+        }
+        if self.fallback_instrument > 0 {
+            warnings.push(format!(
+                "{} notes use instruments missing from the target version and play as harp",
+                self.fallback_instrument
+            ));
+        }
+        if self.uneven_timing {
+            warnings.push(format!(
+                "the song tempo doesn't divide {} ticks per second, {} ticks were merged and \
+                 timing is rounded to game ticks",
+                GAME_TICKS_PER_SECOND, self.merged_ticks
+            ));
+        }
+        warnings
+    }
+}
+
+/// Lowercases a name and replaces characters not allowed in resource locations
+pub fn sanitize_namespace(name: &str) -> String {
+    let namespace: String = name
+        .trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '_' | '-' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+    let namespace = namespace.trim_matches('_');
+
+    if namespace.is_empty() || namespace == "minecraft" {
+        "song".to_string()
+    } else {
+        namespace.to_string()
+    }
+}
+
+/// Quotes a string for JSON
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Relative coordinate for `^` local positions, empty when centered
+fn local_coordinate(value: f32) -> String {
+    if value.abs() < 0.005 {
+        "^".to_string()
+    } else {
+        format!("^{:.2}", value)
+    }
+}
+
+/// Playback rate of a note block sound for a fractional key, 1.0 being F#4
+fn note_block_pitch(key: f32) -> f32 {
+    2f32.powf((key - 45.0) / 12.0)
+}
+
+/// Builds the `tree/*` functions dispatching the timer to the right `notes/*` function, so only
+/// a logarithmic number of score checks run per tick
+fn dispatch(
+    ticks: &[u32],
+    namespace: &str,
+    objective: &str,
+    functions: &mut BTreeMap<String, String>,
+) -> String {
+    if ticks.len() <= DISPATCH_LEAF_SIZE {
+        return ticks
+            .iter()
+            .map(|tick| {
+                format!(
+                    "execute if score @s {} matches {} run function {}:notes/{}\n",
+                    objective, tick, namespace, tick
+                )
+            })
+            .collect();
+    }
+
+    let (low, high) = ticks.split_at(ticks.len() / 2);
+    let mut body = String::new();
+    for half in [low, high] {
+        let (first, last) = (half[0], half[half.len() - 1]);
+        let name = format!("tree/{}_{}", first, last);
+        let child = dispatch(half, namespace, objective, functions);
+        functions.insert(name.clone(), child);
+        body.push_str(&format!(
+            "execute if score @s {} matches {}..{} run function {}:{}\n",
+            objective, first, last, namespace, name
+        ));
+    }
+    body
+}
+
+/// Converts a song to a data pack playing it around the player who runs `<namespace>:play`.
+/// Notes are `/playsound` commands grouped per game tick and driven by a scoreboard timer.
+pub fn export_datapack(
+    song: &NbsFile,
+    options: &DatapackOptions,
+) -> Result<(Vec<u8>, DatapackReport), String> {
+    let song_name = decode_text(&song.header.song_name);
+    let namespace = sanitize_namespace(options.namespace.as_deref().unwrap_or(&song_name));
+    let objective: String = format!("nbs_{}", namespace)
+        .chars()
+        .take(MAX_OBJECTIVE_LENGTH)
+        .collect();
+    let tag = format!("{}.playing", namespace);
+
+    let notes_per_second = (song.header.tempo as f32 / 100.0).max(0.01);
+    let game_ticks_per_tick = GAME_TICKS_PER_SECOND / notes_per_second;
+
+    let mut report = DatapackReport {
+        namespace: namespace.clone(),
+        uneven_timing: (game_ticks_per_tick - game_ticks_per_tick.round()).abs() > 1e-3,
+        ..Default::default()
+    };
+
+    // Commands per game tick
+    let mut ticks: BTreeMap<u32, String> = BTreeMap::new();
+    let mut song_ticks: BTreeSet<u32> = BTreeSet::new();
+
+    for note in &song.notes {
+        if note.instrument >= song.header.default_instruments
+            || minecraft::vanilla_instrument(note.instrument, GameVersion::LATEST).is_none()
+        {
+            report.skipped_custom += 1;
+            continue;
+        }
+        let name = match minecraft::vanilla_instrument(note.instrument, options.version) {
+            Some(instrument) => instrument.name,
+            None => {
+                report.fallback_instrument += 1;
+                minecraft::INSTRUMENTS[0].name
+            }
+        };
+
+        let (layer_volume, layer_panning) = match song.layers.get(note.layer as usize) {
+            Some(layer) => (layer.volume as f32, layer.panning as u8 as f32),
+            None => (100.0, 100.0),
+        };
+
+        // Move the key by octaves, keeping the fine pitch
+        let key = note.key as f32 + note.pitch as f32 / 100.0;
+        let octave_shift = minecraft::transpose_into_range(key.round() as i32) as f32 - key.round();
+        if octave_shift != 0.0 {
+            report.transposed += 1;
+        }
+        let key = (key + octave_shift).clamp(minecraft::MIN_KEY as f32, minecraft::MAX_KEY as f32);
+
+        let volume = (note.velocity as f32 * layer_volume / 10_000.0).clamp(0.0, 1.0);
+        let pan = ((note.panning as u8 as f32 - 100.0) + (layer_panning - 100.0)) / 100.0;
+        let x = -pan.clamp(-1.0, 1.0) * PAN_DISTANCE;
+
+        let game_tick = (note.tick as f32 * game_ticks_per_tick).round() as u32;
+        song_ticks.insert(note.tick as u32);
+
+        ticks.entry(game_tick).or_default().push_str(&format!(
+            "playsound minecraft:block.note_block.{} record @s {} ^ ^ {:.2} {:.4}\n",
+            name,
+            local_coordinate(x),
+            volume,
+            note_block_pitch(key)
+        ));
+        report.notes += 1;
+    }
+
+    report.merged_ticks = song_ticks.len() - ticks.len();
+
+    let mut functions: BTreeMap<String, String> = BTreeMap::new();
+    let tick_list: Vec<u32> = ticks.keys().copied().collect();
+    let end_tick = tick_list.last().copied().unwrap_or(0) + 1;
+
+    let step = format!(
+        "{}scoreboard players add @s {} 1\nexecute if score @s {} matches {}.. run function {}:stop\n",
+        dispatch(&tick_list, &namespace, &objective, &mut functions),
+        objective,
+        objective,
+        end_tick,
+        namespace
+    );
+    functions.insert("step".to_string(), step);
+    functions.insert(
+        "load".to_string(),
+        format!("scoreboard objectives add {} dummy\n", objective),
+    );
+    functions.insert(
+        "play".to_string(),
+        format!(
+            "scoreboard players set @s {} 0\ntag @s add {}\n",
+            objective, tag
+        ),
+    );
+    functions.insert(
+        "stop".to_string(),
+        format!(
+            "tag @s remove {}\nscoreboard players reset @s {}\n",
+            tag, objective
+        ),
+    );
+    functions.insert(
+        "tick".to_string(),
+        format!(
+            "execute as @a[tag={}] at @s run function {}:step\n",
+            tag, namespace
+        ),
+    );
+    for (tick, commands) in ticks {
+        functions.insert(format!("notes/{}", tick), commands);
+    }
+
+    // Pack layout
+    let function_dir = options.version.function_dir();
+    let description = if song_name.is_empty() {
+        "Note block song".to_string()
+    } else {
+        format!("Note block song: {}", song_name)
+    };
+    let mut files = vec![(
+        "pack.mcmeta".to_string(),
+        format!(
+            "{{\"pack\":{{\"pack_format\":{},\"description\":{}}}}}\n",
+            options.version.pack_format(),
+            json_string(&description)
+        )
+        .into_bytes(),
+    )];
+    for (tag_name, function) in [("load", "load"), ("tick", "tick")] {
+        files.push((
+            format!("data/minecraft/tags/{}/{}.json", function_dir, tag_name),
+            format!("{{\"values\":[\"{}:{}\"]}}\n", namespace, function).into_bytes(),
+        ));
+    }
+    for (name, body) in functions {
+        files.push((
+            format!("data/{}/{}/{}.mcfunction", namespace, function_dir, name),
+            body.into_bytes(),
+        ));
+    }
+
+    Ok((pack::write_zip(&files)?, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    fn read_entry(zip: &mut zip::ZipArchive<std::io::Cursor<&[u8]>>, name: &str) -> String {
+        let mut text = String::new();
+        zip.by_name(name)
+            .unwrap_or_else(|_| panic!("missing {}", name))
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    #[test]
+    fn test_sanitize_namespace() {
+        assert_eq!(sanitize_namespace("Nyan Cat!"), "nyan_cat");
+        assert_eq!(sanitize_namespace("  "), "song");
+        assert_eq!(sanitize_namespace("minecraft"), "song");
+    }
+
+    #[test]
+    fn test_note_block_pitch_range() {
+        assert_eq!(note_block_pitch(45.0), 1.0);
+        assert_eq!(note_block_pitch(minecraft::MIN_KEY as f32), 0.5);
+        assert_eq!(note_block_pitch(minecraft::MAX_KEY as f32), 2.0);
+    }
+
+    #[test]
+    fn test_export_datapack() {
+        let nbs_data = include_bytes!("../test-assets/nyan_cat.nbs") as &[u8];
+        let song = crate::song::load_nbs_file(Some(nbs_data)).song;
+        let options = DatapackOptions {
+            version: GameVersion::V1_20,
+            namespace: Some("Nyan".to_string()),
+        };
+
+        let (bytes, report) = export_datapack(&song, &options).unwrap();
+        assert_eq!(report.namespace, "nyan");
+        assert_eq!(
+            report.notes + report.skipped_custom,
+            song.notes.len(),
+            "every note is either played or reported"
+        );
+
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(bytes.as_slice())).unwrap();
+        assert!(read_entry(&mut zip, "pack.mcmeta").contains("\"pack_format\":15"));
+        assert!(
+            read_entry(&mut zip, "data/minecraft/tags/functions/tick.json").contains("nyan:tick")
+        );
+        assert!(read_entry(&mut zip, "data/nyan/functions/step.mcfunction").contains("nyan:stop"));
+
+        let playsounds: usize = (0..zip.len())
+            .map(|index| {
+                let mut entry = zip.by_index(index).unwrap();
+                let mut text = String::new();
+                entry.read_to_string(&mut text).unwrap();
+                text.matches("playsound").count()
+            })
+            .sum();
+        assert_eq!(playsounds, report.notes);
+    }
+
+    #[test]
+    fn test_custom_instruments_follow_the_song_vanilla_count() {
+        let nbs_data = include_bytes!("../test-assets/nyan_cat.nbs") as &[u8];
+        let mut song = crate::song::load_nbs_file(Some(nbs_data)).song;
+        song.header.default_instruments = 10;
+
+        let (_, report) = export_datapack(&song, &DatapackOptions::default()).unwrap();
+        assert_eq!(
+            report.skipped_custom,
+            song.notes
+                .iter()
+                .filter(|note| note.instrument >= 10)
+                .count()
+        );
+    }
+}
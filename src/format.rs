@@ -0,0 +1,157 @@
+use nbs_rs::NbsFile;
+
+use crate::writer;
+
+/// Fields added to the NBS format after the classic layout, with the version introducing them
+const VERSIONED_FIELDS: [(&str, u8); 6] = [
+    ("vanilla instrument count", 1),
+    ("layer panning", 2),
+    ("song length", 3),
+    ("note velocity, panning and pitch", 4),
+    ("layer lock", 4),
+    ("loop settings", 4),
+];
+
+/// Vanilla instruments of classic files, which don't store the count
+pub const CLASSIC_VANILLA_INSTRUMENTS: u8 = 10;
+/// Velocity of notes saved before version 4
+pub const DEFAULT_NOTE_VELOCITY: u8 = 100;
+/// Centered panning of notes saved before version 4 and layers saved before version 2
+pub const DEFAULT_PANNING: u8 = 100;
+/// Fine pitch of notes saved before version 4
+pub const DEFAULT_NOTE_PITCH: i16 = 0;
+
+/// Where a song was read from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SongSource {
+    Nbs,
+    Pack,
+    /// Converted to NBS when loaded
+    Midi,
+}
+
+/// NBS format version a song was saved with, detected from its first bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FormatInfo {
+    /// 0 for the classic format, which has no version byte
+    pub version: u8,
+    pub source: SongSource,
+}
+
+impl FormatInfo {
+    /// New format files start with a zero where classic files have the song length
+    pub fn detect(bytes: &[u8], source: SongSource) -> Self {
+        let version = match bytes {
+            [0, 0, version, ..] => *version,
+            _ => 0,
+        };
+        Self { version, source }
+    }
+
+    pub fn is_classic(&self) -> bool {
+        self.version == 0
+    }
+
+    /// Fields of a newer version are ignored by the parser
+    pub fn is_newer_than_supported(&self) -> bool {
+        self.version > writer::LATEST_VERSION
+    }
+
+    fn has_field(&self, since: u8) -> bool {
+        self.version >= since
+    }
+
+    pub fn has_note_details(&self) -> bool {
+        self.has_field(4)
+    }
+
+    pub fn has_layer_panning(&self) -> bool {
+        self.has_field(2)
+    }
+
+    /// Every versioned field and whether the file has it
+    pub fn fields(&self) -> Vec<(&'static str, bool)> {
+        VERSIONED_FIELDS
+            .iter()
+            .map(|&(name, since)| (name, self.has_field(since)))
+            .collect()
+    }
+
+    /// Short description like `NBS v5 (converted from MIDI)`
+    pub fn describe(&self) -> String {
+        let version = if self.is_classic() {
+            "classic NBS (v0)".to_string()
+        } else if self.is_newer_than_supported() {
+            format!("NBS v{} (newer than supported)", self.version)
+        } else {
+            format!("NBS v{}", self.version)
+        };
+        match self.source {
+            SongSource::Nbs => version,
+            SongSource::Pack => format!("{} in a song pack", version),
+            SongSource::Midi => format!("{} converted from MIDI", version),
+        }
+    }
+}
+
+/// Sets the fields the song's version doesn't store to what Note Block Studio assumes for them
+pub fn apply_defaults(song: &mut NbsFile, format: &FormatInfo) {
+    if format.is_classic() {
+        song.header.default_instruments = CLASSIC_VANILLA_INSTRUMENTS;
+    }
+    if !format.has_note_details() {
+        for note in &mut song.notes {
+            note.velocity = DEFAULT_NOTE_VELOCITY;
+            note.panning = DEFAULT_PANNING as i8;
+            note.pitch = DEFAULT_NOTE_PITCH;
+        }
+    }
+    if !format.has_layer_panning() {
+        for layer in &mut song.layers {
+            layer.panning = DEFAULT_PANNING as i8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_version() {
+        let classic = FormatInfo::detect(&[0x20, 0x01, 0x05, 0x00], SongSource::Nbs);
+        assert!(classic.is_classic());
+        assert!(!classic.has_layer_panning());
+
+        let v5 = FormatInfo::detect(&[0, 0, 5, 16], SongSource::Nbs);
+        assert_eq!(v5.version, 5);
+        assert!(v5.has_note_details());
+        assert!(v5.fields().iter().all(|(_, present)| *present));
+
+        assert!(FormatInfo::detect(&[0, 0, 6], SongSource::Nbs).is_newer_than_supported());
+    }
+
+    #[test]
+    fn test_old_versions_get_defaults() {
+        let bytes = include_bytes!("../test-assets/nyan_cat.nbs");
+        let song = nbs_rs::NbsParser::new(bytes).parse().unwrap();
+
+        for version in 0..4 {
+            let written = writer::write_nbs_version(&song, version);
+            let loaded = crate::song::load_nbs_file(Some(&written));
+
+            assert_eq!(loaded.format.version, version);
+            if version == 0 {
+                assert_eq!(
+                    loaded.song.header.default_instruments,
+                    CLASSIC_VANILLA_INSTRUMENTS
+                );
+            }
+            assert!(loaded.song.notes.iter().all(|note| {
+                note.velocity == DEFAULT_NOTE_VELOCITY
+                    && note.panning as u8 == DEFAULT_PANNING
+                    && note.pitch == DEFAULT_NOTE_PITCH
+            }));
+        }
+    }
+}
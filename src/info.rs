@@ -0,0 +1,331 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{
+    format::SongSource,
+    library::{EntryStatus, Library},
+    pack,
+    song::SongData,
+    stats::{self, SongStats},
+    utils::{decode_text, time_formatter},
+};
+
+#[derive(Debug, Serialize)]
+pub struct FormatField {
+    pub name: &'static str,
+    pub stored: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HeaderInfo {
+    pub name: String,
+    pub author: String,
+    pub original_author: String,
+    pub description: String,
+    /// Ticks per second
+    pub tempo: f32,
+    pub time_signature: u8,
+    pub loop_enabled: bool,
+    pub max_loop_count: u8,
+    pub loop_start_tick: u32,
+    pub auto_save: bool,
+    pub auto_save_duration: u8,
+    pub minutes_spent: u32,
+    pub left_clicks: u32,
+    pub right_clicks: u32,
+    pub blocks_added: u32,
+    pub blocks_removed: u32,
+    /// Name of the MIDI or schematic file the song was imported from
+    pub imported_file: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LayerInfo {
+    pub layer: u32,
+    pub name: String,
+    pub volume: u8,
+    /// -100 is full left, 100 full right
+    pub stereo: i32,
+    pub locked: bool,
+}
+
+/// Where the sample of a custom instrument comes from
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleStatus {
+    InPack,
+    Found(String),
+    Missing,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InstrumentInfo {
+    pub instrument: u8,
+    pub name: String,
+    pub file: String,
+    pub key: u8,
+    pub press_key: bool,
+    pub sample: SampleStatus,
+}
+
+/// Everything `info` reports about a song
+#[derive(Debug, Serialize)]
+pub struct SongInfo {
+    pub path: String,
+    /// Song name, or the best file name when the song has none
+    pub title: String,
+    pub format: String,
+    pub format_version: u8,
+    pub format_fields: Vec<FormatField>,
+    pub header: HeaderInfo,
+    pub layers: Vec<LayerInfo>,
+    pub custom_instruments: Vec<InstrumentInfo>,
+    pub stats: SongStats,
+    /// Whether `library scan` indexed the song
+    pub library: EntryStatus,
+}
+
+/// Resolves the sample of every custom instrument, in the pack for song packs and next to the
+/// song otherwise
+pub fn sample_statuses(path: &Path, bytes: &[u8], song_data: &SongData) -> Vec<SampleStatus> {
+    let pack_files = match song_data.format.source {
+        SongSource::Pack => pack::zip_file_names(bytes),
+        _ => Vec::new(),
+    };
+    let search_dirs = pack::sound_search_dirs(path, &[]);
+
+    song_data
+        .song
+        .instruments
+        .iter()
+        .map(|instrument| {
+            let name = decode_text(&instrument.name);
+            let file = decode_text(&instrument.file);
+            if pack_files.contains(&pack::pack_sound_path(&pack::pack_sound_name(&file, &name))) {
+                SampleStatus::InPack
+            } else {
+                match pack::find_sound(&file, &name, &search_dirs) {
+                    Some(found) => SampleStatus::Found(found.display().to_string()),
+                    None => SampleStatus::Missing,
+                }
+            }
+        })
+        .collect()
+}
+
+/// Collects the song's metadata and where it stands in the library
+pub fn song_info(path: &Path, bytes: &[u8], song_data: &SongData, library: &Library) -> SongInfo {
+    let song = &song_data.song;
+    let header = &song.header;
+
+    let custom_instruments = song
+        .instruments
+        .iter()
+        .zip(sample_statuses(path, bytes, song_data))
+        .enumerate()
+        .map(|(index, (instrument, sample))| InstrumentInfo {
+            instrument: (header.default_instruments as usize + index) as u8,
+            name: decode_text(&instrument.name),
+            file: decode_text(&instrument.file),
+            key: instrument.key,
+            press_key: instrument.press_key != 0,
+            sample,
+        })
+        .collect();
+
+    SongInfo {
+        path: path.display().to_string(),
+        title: song_data.title(),
+        format: song_data.format.describe(),
+        format_version: song_data.format.version,
+        format_fields: song_data
+            .format
+            .fields()
+            .into_iter()
+            .map(|(name, stored)| FormatField { name, stored })
+            .collect(),
+        header: HeaderInfo {
+            name: decode_text(&header.song_name),
+            author: decode_text(&header.song_author),
+            original_author: decode_text(&header.original_author),
+            description: decode_text(&header.description),
+            tempo: header.tempo as f32 / 100.0,
+            time_signature: header.time_signature,
+            loop_enabled: header.loop_on_off != 0,
+            max_loop_count: header.max_loop_count,
+            loop_start_tick: header.loop_start_tick as u32,
+            auto_save: header.auto_save != 0,
+            auto_save_duration: header.auto_save_duration,
+            minutes_spent: header.minutes_spent as u32,
+            left_clicks: header.left_clicks as u32,
+            right_clicks: header.right_clicks as u32,
+            blocks_added: header.blocks_added as u32,
+            blocks_removed: header.blocks_removed as u32,
+            imported_file: decode_text(&header.song_origin),
+        },
+        layers: song
+            .layers
+            .iter()
+            .enumerate()
+            .map(|(index, layer)| LayerInfo {
+                layer: index as u32,
+                name: decode_text(&layer.name),
+                volume: layer.volume,
+                stereo: layer.panning as u8 as i32 - 100,
+                locked: layer.lock != 0,
+            })
+            .collect(),
+        custom_instruments,
+        stats: stats::compute(song),
+        library: library.entry_status(path),
+    }
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value { "yes" } else { "no" }
+}
+
+/// Prints the info as aligned text
+pub fn print_info(info: &SongInfo) {
+    let header = &info.header;
+    let stats = &info.stats;
+
+    println!("File:               {}", info.path);
+    println!("Title:              {}", info.title);
+    println!("Format:             {}", info.format);
+    for field in &info.format_fields {
+        println!(
+            "    {:<34}{}",
+            field.name,
+            if field.stored {
+                "stored"
+            } else {
+                "not stored, defaults used"
+            }
+        );
+    }
+
+    println!();
+    println!("Name:               {}", header.name);
+    println!("Author:             {}", header.author);
+    println!("Original author:    {}", header.original_author);
+    println!("Description:        {}", header.description);
+    println!("Tempo:              {:.2} ticks per second", header.tempo);
+    println!("Time signature:     {}/4", header.time_signature);
+    println!(
+        "Loop:               {}, {} times from tick {}",
+        if header.loop_enabled { "on" } else { "off" },
+        header.max_loop_count,
+        header.loop_start_tick
+    );
+    println!(
+        "Auto save:          {}, every {} minutes",
+        yes_no(header.auto_save),
+        header.auto_save_duration
+    );
+    println!("Minutes spent:      {}", header.minutes_spent);
+    println!(
+        "Clicks:             {} left, {} right",
+        header.left_clicks, header.right_clicks
+    );
+    println!(
+        "Blocks:             {} added, {} removed",
+        header.blocks_added, header.blocks_removed
+    );
+    println!("Imported from:      {}", header.imported_file);
+    println!(
+        "Library:            {}",
+        match info.library {
+            EntryStatus::NotIndexed => "not indexed",
+            EntryStatus::UpToDate => "indexed",
+            EntryStatus::Outdated => "changed since indexed, run library scan again",
+        }
+    );
+
+    println!();
+    println!("Layers ({}):", info.layers.len());
+    for layer in &info.layers {
+        let notes = stats
+            .notes_per_layer
+            .iter()
+            .find(|count| count.layer == layer.layer)
+            .map_or(0, |count| count.notes);
+        println!(
+            "    {:>3} {:<24} volume {:>3}%  stereo {:>+4}  {:>6} notes{}",
+            layer.layer + 1,
+            layer.name,
+            layer.volume,
+            layer.stereo,
+            notes,
+            if layer.locked { "  locked" } else { "" }
+        );
+    }
+
+    println!();
+    println!("Custom instruments ({}):", info.custom_instruments.len());
+    for instrument in &info.custom_instruments {
+        println!(
+            "    {:>3} {:<24} {} key {}  sample: {}",
+            instrument.instrument,
+            instrument.name,
+            instrument.file,
+            instrument.key,
+            match &instrument.sample {
+                SampleStatus::InPack => "in pack".to_string(),
+                SampleStatus::Found(path) => path.clone(),
+                SampleStatus::Missing => "missing".to_string(),
+            }
+        );
+    }
+
+    println!();
+    println!("Notes:              {}", stats.notes);
+    for count in &stats.notes_per_instrument {
+        println!("    {:<24}{:>6}", count.name, count.notes);
+    }
+    match (stats.lowest_key, stats.highest_key) {
+        (Some(lowest), Some(highest)) => println!("Key range:          {} to {}", lowest, highest),
+        _ => println!("Key range:          none"),
+    }
+    println!(
+        "Max polyphony:      {} notes at tick {}",
+        stats.max_polyphony, stats.max_polyphony_tick
+    );
+    println!(
+        "Duration:           {} ({} ticks)",
+        time_formatter(stats.duration_seconds),
+        stats.length_ticks
+    );
+}
+
+#[cfg(all(test, feature = "zip"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_samples_are_in_pack() {
+        let bytes = include_bytes!("../test-assets/Mesmerizer.zip") as &[u8];
+        let song_data = crate::song::load_nbs_file(Some(bytes));
+        let info = song_info(
+            Path::new("Mesmerizer.zip"),
+            bytes,
+            &song_data,
+            &Library::default(),
+        );
+
+        assert_eq!(
+            info.custom_instruments.len(),
+            song_data.song.instruments.len()
+        );
+        assert!(
+            info.custom_instruments
+                .iter()
+                .all(|instrument| matches!(instrument.sample, SampleStatus::InPack))
+        );
+
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["stats"]["notes"], song_data.song.notes.len());
+        assert_eq!(json["library"], "not_indexed");
+    }
+}
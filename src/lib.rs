@@ -0,0 +1,43 @@
+// Playback core shared by the player and other tools: song loading, the note model, scheduling
+// and, behind the `audio` feature, the kira engine. The `gui` feature adds the macroquad views
+// and `zip` song packs.
+
+#[cfg(feature = "audio")]
+pub mod audio;
+#[cfg(feature = "gui")]
+pub mod browser;
+#[cfg(all(feature = "gui", feature = "audio"))]
+pub mod calibration;
+#[cfg(feature = "gui")]
+pub mod canvas;
+#[cfg(feature = "zip")]
+pub mod datapack;
+#[cfg(feature = "gui")]
+pub mod font;
+pub mod format;
+pub mod info;
+pub mod library;
+pub mod lint;
+pub mod midi;
+pub mod minecraft;
+#[cfg(feature = "audio")]
+pub mod mixer;
+pub mod nbt;
+pub mod note;
+#[cfg(feature = "gui")]
+pub mod overlay;
+pub mod pack;
+#[cfg(feature = "gui")]
+pub mod piano;
+pub mod playback;
+pub mod playlist;
+#[cfg(feature = "audio")]
+pub mod resample;
+pub mod scheduler;
+pub mod schematic;
+pub mod sink;
+pub mod song;
+pub mod stats;
+pub mod transport;
+pub mod utils;
+pub mod writer;
@@ -0,0 +1,379 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    playlist, song,
+    stats::{self, SongStats},
+    utils::decode_text,
+};
+
+/// Bumped when the entries change, older indexes being rebuilt
+const LIBRARY_VERSION: u32 = 1;
+const LIBRARY_FILE_NAME: &str = "library.json";
+/// Files the library indexes
+pub const INDEXED_EXTENSIONS: [&str; 2] = ["nbs", "zip"];
+
+/// 64-bit FNV-1a hash, to tell whether a touched file really changed
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Cached metadata of an indexed song
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LibraryEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch
+    pub modified: u64,
+    pub hash: String,
+    pub title: String,
+    pub author: String,
+    pub original_author: String,
+    pub description: String,
+    /// Ticks per second
+    pub tempo: f32,
+    pub format: String,
+    pub custom_instruments: Vec<String>,
+    pub stats: SongStats,
+}
+
+impl LibraryEntry {
+    fn read(path: PathBuf, size: u64, modified: u64, bytes: &[u8]) -> Result<Self, String> {
+        let song_data = song::load_song_metadata(bytes, &path)?;
+        let header = &song_data.song.header;
+
+        Ok(Self {
+            size,
+            modified,
+            hash: format!("{:016x}", fnv1a(bytes)),
+            title: song_data.title(),
+            author: decode_text(&header.song_author).trim().to_string(),
+            original_author: decode_text(&header.original_author).trim().to_string(),
+            description: decode_text(&header.description).trim().to_string(),
+            tempo: header.tempo as f32 / 100.0,
+            format: song_data.format.describe(),
+            custom_instruments: song_data
+                .song
+                .instruments
+                .iter()
+                .map(|instrument| decode_text(&instrument.name))
+                .collect(),
+            stats: stats::compute(&song_data.song),
+            path,
+        })
+    }
+
+    pub fn credits(&self) -> Option<String> {
+        song::credits(&self.author, &self.original_author)
+    }
+
+    fn matches(&self, query: &LibraryQuery) -> bool {
+        let text = format!(
+            "{} {} {} {}",
+            self.title,
+            self.author,
+            self.original_author,
+            self.path.file_name().unwrap_or_default().to_string_lossy()
+        )
+        .to_lowercase();
+        let duration = self.stats.duration_seconds;
+
+        query
+            .text
+            .to_lowercase()
+            .split_whitespace()
+            .all(|word| text.contains(word))
+            && query.instrument.as_ref().is_none_or(|instrument| {
+                let instrument = instrument.to_lowercase();
+                self.stats
+                    .notes_per_instrument
+                    .iter()
+                    .any(|count| count.name.to_lowercase().contains(&instrument))
+            })
+            && query.min_duration.is_none_or(|min| duration >= min)
+            && query.max_duration.is_none_or(|max| duration <= max)
+    }
+}
+
+/// Filters of a library search, every set filter having to match
+#[derive(Debug, Default)]
+pub struct LibraryQuery {
+    /// Words to find in the title, authors or file name
+    pub text: String,
+    /// Part of the name of an instrument the song uses
+    pub instrument: Option<String>,
+    /// Duration bounds in seconds
+    pub min_duration: Option<f32>,
+    pub max_duration: Option<f32>,
+}
+
+/// What happened to a file when the library was refreshed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Refresh {
+    Added,
+    Updated,
+    Unchanged,
+}
+
+/// Whether a file is in the library and its entry still matches it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryStatus {
+    NotIndexed,
+    UpToDate,
+    /// The file changed since it was indexed
+    Outdated,
+}
+
+#[derive(Debug, Default)]
+pub struct RefreshReport {
+    pub added: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    /// Files that couldn't be read, with the reason
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// Index of song metadata, saved as JSON so folders don't need to be parsed again
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Library {
+    version: u32,
+    songs: Vec<LibraryEntry>,
+}
+
+impl Default for Library {
+    fn default() -> Self {
+        Self {
+            version: LIBRARY_VERSION,
+            songs: Vec::new(),
+        }
+    }
+}
+
+/// Absolute form of a path, so the same song is indexed once
+fn library_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Size and modification time in seconds since the Unix epoch of a file
+fn file_stamp(path: &Path) -> Result<(u64, u64), String> {
+    let metadata =
+        fs::metadata(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_secs());
+    Ok((metadata.len(), modified))
+}
+
+impl Library {
+    /// Index location in the user data folder
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("nbs-player-rs").join(LIBRARY_FILE_NAME))
+    }
+
+    /// Reads an index, starting a new one when it is missing, broken or outdated
+    pub fn load(path: &Path) -> Self {
+        let Ok(text) = fs::read_to_string(path) else {
+            return Self::default();
+        };
+        match serde_json::from_str::<Library>(&text) {
+            Ok(library) if library.version == LIBRARY_VERSION => library,
+            Ok(_) => {
+                log::info!("Rebuilding outdated library index {}", path.display());
+                Self::default()
+            }
+            Err(error) => {
+                log::warn!(
+                    "Ignoring broken library index {}: {}",
+                    path.display(),
+                    error
+                );
+                Self::default()
+            }
+        }
+    }
+
+    /// Reads the index at the default location
+    pub fn load_default() -> Self {
+        Self::default_path()
+            .map(|path| Self::load(&path))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        let text = serde_json::to_string(self)
+            .map_err(|e| format!("Failed to serialize the library: {}", e))?;
+        fs::write(path, text).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// Saves the index at the default location
+    pub fn save_default(&self) -> Result<(), String> {
+        let path = Self::default_path().ok_or("No user data folder to save the library in")?;
+        self.save(&path)
+    }
+
+    pub fn songs(&self) -> &[LibraryEntry] {
+        &self.songs
+    }
+
+    pub fn entry(&self, path: &Path) -> Option<&LibraryEntry> {
+        let path = library_path(path);
+        self.songs.iter().find(|entry| entry.path == path)
+    }
+
+    pub fn entry_status(&self, path: &Path) -> EntryStatus {
+        match self.entry(path) {
+            None => EntryStatus::NotIndexed,
+            Some(entry) if file_stamp(&entry.path) == Ok((entry.size, entry.modified)) => {
+                EntryStatus::UpToDate
+            }
+            Some(_) => EntryStatus::Outdated,
+        }
+    }
+
+    /// Indexes a file, reading it again only when its size or modification time changed
+    pub fn update_file(&mut self, path: &Path) -> Result<Refresh, String> {
+        let path = library_path(path);
+        let (size, modified) = file_stamp(&path)?;
+
+        let index = self.songs.iter().position(|entry| entry.path == path);
+        if let Some(index) = index
+            && self.songs[index].size == size
+            && self.songs[index].modified == modified
+        {
+            return Ok(Refresh::Unchanged);
+        }
+
+        let bytes =
+            fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        match index {
+            // Touched but not edited, keep the cached metadata
+            Some(index) if self.songs[index].hash == format!("{:016x}", fnv1a(&bytes)) => {
+                self.songs[index].modified = modified;
+                Ok(Refresh::Unchanged)
+            }
+            Some(index) => {
+                self.songs[index] = LibraryEntry::read(path, size, modified, &bytes)?;
+                Ok(Refresh::Updated)
+            }
+            None => {
+                self.songs
+                    .push(LibraryEntry::read(path, size, modified, &bytes)?);
+                Ok(Refresh::Added)
+            }
+        }
+    }
+
+    /// Indexes the songs of a folder and forgets the ones deleted from it
+    pub fn refresh_dir(&mut self, dir: &Path) -> Result<RefreshReport, String> {
+        let mut report = RefreshReport::default();
+
+        for path in playlist::songs_in_dir(dir, &INDEXED_EXTENSIONS)? {
+            match self.update_file(&path) {
+                Ok(Refresh::Added) => report.added += 1,
+                Ok(Refresh::Updated) => report.updated += 1,
+                Ok(Refresh::Unchanged) => report.unchanged += 1,
+                Err(error) => report.failed.push((path, error)),
+            }
+        }
+
+        report.removed = self.forget_missing(dir);
+        Ok(report)
+    }
+
+    /// Drops the songs of a folder whose file is gone, returning how many were dropped
+    pub fn forget_missing(&mut self, dir: &Path) -> usize {
+        let dir = library_path(dir);
+        let before = self.songs.len();
+        self.songs
+            .retain(|entry| entry.path.parent() != Some(dir.as_path()) || entry.path.exists());
+        before - self.songs.len()
+    }
+
+    /// Indexed songs matching a query, sorted by title
+    pub fn search(&self, query: &LibraryQuery) -> Vec<&LibraryEntry> {
+        let mut found: Vec<&LibraryEntry> = self
+            .songs
+            .iter()
+            .filter(|entry| entry.matches(query))
+            .collect();
+        found.sort_by_key(|entry| entry.title.to_lowercase());
+        found
+    }
+
+    /// Files of the songs matching a query, to play them as a playlist
+    pub fn search_paths(&self, query: &LibraryQuery) -> Vec<PathBuf> {
+        self.search(query)
+            .into_iter()
+            .map(|entry| entry.path.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn test_refresh_and_search() {
+        let dir = std::env::temp_dir().join(format!("nbs-library-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let song_path = dir.join("nyan_cat.nbs");
+        fs::write(&song_path, include_bytes!("../test-assets/nyan_cat.nbs")).unwrap();
+
+        let mut library = Library::default();
+        let report = library.refresh_dir(&dir).unwrap();
+        assert_eq!(report.added, 1);
+        assert_eq!(library.refresh_dir(&dir).unwrap().unchanged, 1);
+
+        let entry = library.entry(&song_path).unwrap();
+        assert!(entry.stats.notes > 0);
+        assert_eq!(library.entry_status(&song_path), EntryStatus::UpToDate);
+        assert_eq!(
+            library.entry_status(&dir.join("missing.nbs")),
+            EntryStatus::NotIndexed
+        );
+        let everything = LibraryQuery::default();
+        assert_eq!(library.search(&everything).len(), 1);
+        assert_eq!(
+            library.search_paths(&everything),
+            vec![library_path(&song_path)]
+        );
+        let too_short = LibraryQuery {
+            max_duration: Some(entry.stats.duration_seconds - 1.),
+            ..Default::default()
+        };
+        assert!(library.search(&too_short).is_empty());
+
+        let index_path = dir.join(LIBRARY_FILE_NAME);
+        library.save(&index_path).unwrap();
+        assert_eq!(Library::load(&index_path).songs().len(), 1);
+
+        fs::write(&song_path, b"edited").unwrap();
+        assert_eq!(library.entry_status(&song_path), EntryStatus::Outdated);
+        fs::remove_file(&song_path).unwrap();
+        assert_eq!(library.refresh_dir(&dir).unwrap().removed, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::HashSet;
+
+use nbs_rs::NbsFile;
+
+use crate::{info::SampleStatus, minecraft, utils::decode_text};
+
+/// Highest key shown on the player's piano (C8), the lowest being A0 at key 0
+const PIANO_HIGHEST_KEY: u8 = 87;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IssueKind {
+    /// Note after `song_length`, the song's last tick, which the player never reaches
+    NoteBeyondLength,
+    /// Note on a layer missing from the layer list, played without layer volume and panning
+    MissingLayer,
+    KeyOutsidePiano,
+    /// Note a vanilla note block can't play without transposing
+    KeyOutsideMinecraft,
+    /// Instrument id past the custom instruments
+    UnknownInstrument,
+    /// Custom instrument whose sample can't be found
+    MissingSample,
+    /// Two notes on the same tick and layer
+    DuplicateNote,
+    /// Layer at zero volume
+    SilentLayer,
+}
+
+impl IssueKind {
+    pub fn severity(self) -> Severity {
+        match self {
+            IssueKind::KeyOutsideMinecraft | IssueKind::SilentLayer => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub message: String,
+}
+
+impl Issue {
+    fn new(kind: IssueKind, message: String) -> Self {
+        Self { kind, message }
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.kind.severity()
+    }
+}
+
+/// Looks for problems in a song, `samples` being the sample status of each custom instrument
+pub fn check(song: &NbsFile, samples: &[SampleStatus]) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let song_length = song.header.song_length as u32;
+    let first_custom = song.header.default_instruments as usize;
+    let instrument_count = first_custom + song.instruments.len();
+    let mut occupied = HashSet::new();
+
+    for note in &song.notes {
+        let (tick, layer) = (note.tick as u32, note.layer as u32);
+        let position = format!("tick {} layer {}", tick, layer + 1);
+
+        if song_length > 0 && tick > song_length {
+            issues.push(Issue::new(
+                IssueKind::NoteBeyondLength,
+                format!(
+                    "{}: note after the end of the song (last tick {})",
+                    position, song_length
+                ),
+            ));
+        }
+        if layer as usize >= song.layers.len() {
+            issues.push(Issue::new(
+                IssueKind::MissingLayer,
+                format!(
+                    "{}: note on a layer past the {} saved layers",
+                    position,
+                    song.layers.len()
+                ),
+            ));
+        }
+        if note.key > PIANO_HIGHEST_KEY {
+            issues.push(Issue::new(
+                IssueKind::KeyOutsidePiano,
+                format!("{}: key {} is above the piano (C8)", position, note.key),
+            ));
+        } else if !(minecraft::MIN_KEY..=minecraft::MAX_KEY).contains(&note.key) {
+            issues.push(Issue::new(
+                IssueKind::KeyOutsideMinecraft,
+                format!(
+                    "{}: key {} is outside the note block range (F#3 to F#5)",
+                    position, note.key
+                ),
+            ));
+        }
+        if note.instrument as usize >= instrument_count {
+            issues.push(Issue::new(
+                IssueKind::UnknownInstrument,
+                format!(
+                    "{}: instrument {} doesn't exist, the song has {} instruments",
+                    position, note.instrument, instrument_count
+                ),
+            ));
+        }
+        if !occupied.insert((tick, layer)) {
+            issues.push(Issue::new(
+                IssueKind::DuplicateNote,
+                format!("{}: more than one note", position),
+            ));
+        }
+    }
+
+    for (index, (instrument, sample)) in song.instruments.iter().zip(samples).enumerate() {
+        if matches!(sample, SampleStatus::Missing) {
+            issues.push(Issue::new(
+                IssueKind::MissingSample,
+                format!(
+                    "instrument {} ({}): sample {} not found",
+                    first_custom + index,
+                    decode_text(&instrument.name),
+                    decode_text(&instrument.file)
+                ),
+            ));
+        }
+    }
+
+    for (index, layer) in song.layers.iter().enumerate() {
+        if layer.volume == 0 {
+            issues.push(Issue::new(
+                IssueKind::SilentLayer,
+                format!(
+                    "layer {} ({}): volume is 0",
+                    index + 1,
+                    decode_text(&layer.name)
+                ),
+            ));
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(issues: &[Issue]) -> Vec<IssueKind> {
+        issues.iter().map(|issue| issue.kind).collect()
+    }
+
+    #[test]
+    fn test_saved_songs_have_no_errors() {
+        let songs: [&[u8]; 6] = [
+            include_bytes!("../test-assets/nyan_cat.nbs"),
+            include_bytes!("../test-assets/turkish_march.nbs"),
+            include_bytes!("../test-assets/Bad Piggies Theme.nbs"),
+            include_bytes!("../test-assets/Metropolis of Illusion.nbs"),
+            include_bytes!("../test-assets/Note Block Megacollab.nbs"),
+            include_bytes!("../test-assets/Rush E.nbs"),
+        ];
+        for bytes in songs {
+            let song = crate::song::load_nbs_file(Some(bytes)).song;
+            let samples = vec![SampleStatus::InPack; song.instruments.len()];
+            let errors: Vec<String> = check(&song, &samples)
+                .into_iter()
+                .filter(|issue| issue.severity() == Severity::Error)
+                .map(|issue| issue.message)
+                .collect();
+            assert!(errors.is_empty(), "{:?}", errors);
+        }
+    }
+
+    #[test]
+    fn test_check_finds_problems() {
+        let nbs_data = include_bytes!("../test-assets/nyan_cat.nbs") as &[u8];
+        let mut song = crate::song::load_nbs_file(Some(nbs_data)).song;
+        let samples = vec![SampleStatus::Missing; song.instruments.len()];
+        let before = kinds(&check(&song, &samples));
+
+        song.notes[0].key = PIANO_HIGHEST_KEY + 1;
+        song.notes[1].tick = song.notes[2].tick;
+        song.notes[1].layer = song.notes[2].layer;
+        song.layers[0].volume = 0;
+        song.notes[3].tick = (song.header.song_length + 1) as _;
+        let after = kinds(&check(&song, &samples));
+
+        assert!(!before.contains(&IssueKind::KeyOutsidePiano));
+        assert!(after.contains(&IssueKind::KeyOutsidePiano));
+        assert!(after.contains(&IssueKind::DuplicateNote));
+        assert!(after.contains(&IssueKind::SilentLayer));
+        assert!(!before.contains(&IssueKind::NoteBeyondLength));
+        assert!(after.contains(&IssueKind::NoteBeyondLength));
+    }
+
+    #[test]
+    fn test_instruments_follow_the_song_vanilla_count() {
+        let nbs_data = include_bytes!("../test-assets/nyan_cat.nbs") as &[u8];
+        let mut song = crate::song::load_nbs_file(Some(nbs_data)).song;
+        let samples = vec![SampleStatus::Missing; song.instruments.len()];
+
+        // The same song saved when there were 10 vanilla instruments
+        song.header.default_instruments = 10;
+        song.notes
+            .retain(|note| !(10..16).contains(&note.instrument));
+        for note in &mut song.notes {
+            if note.instrument >= 16 {
+                note.instrument -= 6;
+            }
+        }
+        let issues = check(&song, &samples);
+        assert!(!kinds(&issues).contains(&IssueKind::UnknownInstrument));
+        assert!(
+            issues
+                .iter()
+                .any(|issue| issue.message.starts_with("instrument 10 "))
+        );
+
+        song.notes[0].instrument = 10 + song.instruments.len() as u8;
+        assert!(kinds(&check(&song, &samples)).contains(&IssueKind::UnknownInstrument));
+    }
+
+    #[test]
+    #[cfg(feature = "zip")]
+    fn test_missing_samples_are_errors() {
+        let pack = include_bytes!("../test-assets/Mesmerizer.zip") as &[u8];
+        let song = crate::song::load_nbs_file(Some(pack)).song;
+        let samples = vec![SampleStatus::Missing; song.instruments.len()];
+
+        let issues = check(&song, &samples);
+        assert_eq!(
+            issues
+                .iter()
+                .filter(|issue| issue.kind == IssueKind::MissingSample)
+                .count(),
+            song.instruments.len()
+        );
+        assert!(
+            issues
+                .iter()
+                .filter(|issue| issue.kind == IssueKind::MissingSample)
+                .all(|issue| issue.severity() == Severity::Error)
+        );
+    }
+}
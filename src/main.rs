@@ -1,69 +1,300 @@
 use macroquad::{
-    self, color,
-    input::{KeyCode, MouseButton, is_key_pressed, is_mouse_button_pressed},
-    text::{TextParams, draw_text_ex, load_ttf_font_from_bytes, measure_text},
+    self, DroppedFile,
+    color::{self, Color},
+    input::{KeyCode, MouseButton, get_dropped_files, is_key_pressed, is_mouse_button_pressed},
+    shapes::draw_rectangle,
+    text::{Font, TextParams, draw_text_ex, load_ttf_font_from_bytes, measure_text},
     time::{get_fps, get_frame_time},
     window::{self, clear_background, request_new_screen_size},
 };
+use nbs_player::{
+    audio, browser, calibration, canvas, datapack, font, info, library, lint, midi, minecraft,
+    note, overlay, pack, piano, playback, playlist, resample, schematic, song, stats, transport,
+    utils, writer,
+};
+use nbs_rs::NbsFile;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+use transport::TransportCommand;
 use utils::time_formatter;
 
-mod audio;
-mod font;
-mod note;
-mod piano;
-mod song;
-mod utils;
+mod cli;
+mod web;
+
+/// Maximum note length when fading out long samples, in seconds
+const SUSTAIN_MAX_DURATION: f64 = 1.0;
+/// Fade-out time when a note is cut short, in seconds
+const SUSTAIN_RELEASE: f64 = 0.15;
+/// Seconds skipped by the arrow keys
+const SEEK_STEP: f32 = 5.0;
+/// How long load errors stay on screen, in seconds
+const STATUS_MESSAGE_DURATION: f32 = 5.0;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match cli::parse_args(&args) {
+        Ok(cli::Command::Play {
+            songs,
+            options,
+            shuffle,
+            repeat,
+            browse,
+            library,
+        }) => {
+            let mut songs = songs;
+            if let Some(query) = &library {
+                let found = library::Library::load_default().search_paths(query);
+                if found.is_empty() {
+                    eprintln!("Error: No indexed songs match \"{}\"", query.text);
+                    return ExitCode::FAILURE;
+                }
+                songs.extend(found);
+            }
+            let mut playlist = match playlist::Playlist::from_paths(&songs) {
+                Ok(playlist) => playlist,
+                Err(error) => {
+                    eprintln!("Error: {}", error);
+                    return ExitCode::FAILURE;
+                }
+            };
+            if !songs.is_empty() && playlist.is_empty() {
+                eprintln!("Error: No songs to play");
+                return ExitCode::FAILURE;
+            }
+            playlist.set_repeat(repeat);
+            macroquad::Window::new("BasicShapes", play(playlist, shuffle, browse, options));
+            ExitCode::SUCCESS
+        }
+        Ok(command) => cli::run(command),
+        Err(error) => {
+            eprintln!("Error: {}\n\n{}", error, cli::USAGE);
+            ExitCode::from(2)
+        }
+    }
+}
 
-#[macroquad::main("BasicShapes")]
-async fn main() {
-    let mut window_width = 1280.;
-    let mut window_height = 720.;
+/// Player state of the song being played, rebuilt when the playlist moves to another song
+struct LoadedSong {
+    title: String,
+    format_text: String,
+    info_overlay: overlay::SongInfoOverlay,
+    nbs_file: NbsFile,
+    note_blocks: Vec<Vec<note::NoteBlock>>,
+}
 
-    request_new_screen_size(window_width, window_height);
+impl LoadedSong {
+    /// Takes over a loaded song, replacing the engine's custom sounds with the song's own
+    fn new(song_data: song::SongData, audio_engine: &mut audio::AudioEngine) -> Self {
+        let title = song_data.full_title();
+        let format_text = format!("Format: {} (I for song info)", song_data.format.describe());
+        let info_overlay = overlay::SongInfoOverlay::new(&song_data);
+        web::publish_metadata(&song_data);
+
+        if song_data.extra_sounds.iter().all(Option::is_none) {
+            log::warn!("No extra sounds loaded");
+        } else {
+            println!("{:?}", song_data.song.instruments);
+        }
+        audio_engine.set_extra_sounds(
+            song_data.song.header.default_instruments,
+            &song_data.extra_sounds,
+        );
+
+        let nbs_file = song_data.song;
+        let note_blocks = note::get_note_blocks(&nbs_file);
+        if audio_engine.quality() == resample::ResampleQuality::HighQuality {
+            audio_engine.prepare_notes(&note_blocks);
+        }
+
+        Self {
+            title,
+            format_text,
+            info_overlay,
+            nbs_file,
+            note_blocks,
+        }
+    }
+
+    /// Length in ticks and ticks per second
+    fn timing(&self) -> (usize, f32) {
+        (
+            note::song_ticks(&self.nbs_file),
+            self.nbs_file.header.tempo as f32 / 100.0,
+        )
+    }
+
+    /// Moves the playback to the start of this song and plays it
+    fn start(&self, playback: &mut playback::Playback) {
+        let (song_length, notes_per_second) = self.timing();
+        playback.load(song_length, notes_per_second);
+        playback.transport.start();
+    }
+
+    /// Lets the piano keys light up again after the position jumped
+    fn reset_notes(&mut self) {
+        self.note_blocks = note::get_note_blocks(&self.nbs_file);
+    }
+}
+
+/// Message shown over the player for a while, e.g. when a song fails to load
+struct StatusMessage {
+    text: String,
+    /// Seconds left before it disappears
+    remaining: f32,
+}
+
+impl StatusMessage {
+    fn error(text: String) -> Self {
+        log::error!("{}", text);
+        Self {
+            text,
+            remaining: STATUS_MESSAGE_DURATION,
+        }
+    }
+}
+
+/// Draws the status message centered at the top of the window
+fn draw_status_message(status: &Option<StatusMessage>, window_width: f32, font: &Font) {
+    let Some(message) = status else {
+        return;
+    };
+    let font_size = 20;
+    let text_width = measure_text(&message.text, Some(font), font_size, 1.0).width;
+    let x = ((window_width - text_width) / 2.).max(10.);
+    draw_rectangle(
+        x - 10.,
+        40.,
+        text_width + 20.,
+        34.,
+        Color::new(1., 1., 1., 0.9),
+    );
+    draw_text_ex(
+        &message.text,
+        x,
+        63.,
+        TextParams {
+            font_size,
+            font: Some(font),
+            color: color::RED,
+            ..Default::default()
+        },
+    );
+}
 
-    let nbs_data = song::load_nbs_file(None);
+/// Loads a song file, reading it from disk unless its bytes are given
+fn read_song(
+    path: &Path,
+    bytes: Option<Vec<u8>>,
+    options: &song::LoadOptions,
+) -> Result<song::SongData, String> {
+    let bytes = match bytes {
+        Some(bytes) => bytes,
+        None => fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?,
+    };
+    song::load_song_file(&bytes, path, options)
+        .map_err(|e| format!("Failed to load {}: {}", path.display(), e))
+}
 
-    let nbs_file = nbs_data.song;
-    let extra_sounds = nbs_data.extra_sounds;
+/// Loads the current song of the playlist, dropping songs that can't be loaded, or the bundled
+/// song when the playlist is empty
+fn load_playlist_song(
+    playlist: &mut playlist::Playlist,
+    options: &song::LoadOptions,
+    status: &mut Option<StatusMessage>,
+) -> song::SongData {
+    while let Some(path) = playlist.current() {
+        match read_song(path, None, options) {
+            Ok(song_data) => return song_data,
+            Err(error) => {
+                *status = Some(StatusMessage::error(error));
+                playlist.remove_current();
+            }
+        }
+    }
+    song::load_nbs_file(None)
+}
 
-    if extra_sounds.len() == 0 {
-        log::warn!("No extra sounds loaded");
-    } else {
-        println!("{:?}", nbs_file.instruments);
+/// Loads a song dropped on the window, native builds giving its path and web builds its bytes
+fn load_dropped_song(
+    file: DroppedFile,
+    options: &song::LoadOptions,
+) -> Result<song::SongData, String> {
+    let path = file.path.unwrap_or_else(|| PathBuf::from("dropped file"));
+    if !playlist::has_extension(&path, &playlist::SONG_EXTENSIONS) {
+        return Err(format!(
+            "Can't play {}, drop a .nbs, .zip or .mid song",
+            path.display()
+        ));
     }
+    read_song(&path, file.bytes, options)
+}
 
-    let song_name: String = String::from_utf8(nbs_file.header.song_name.clone()).unwrap();
-    let song_author: String = String::from_utf8(nbs_file.header.song_author.clone()).unwrap();
-    let title: String = format!("{} - {}", song_name, song_author);
-    let notes_per_second: f32 = nbs_file.header.tempo as f32 / 100.0;
-    let total_duration: f32 = nbs_file.header.song_length as f32 / notes_per_second;
+async fn play(
+    mut playlist: playlist::Playlist,
+    shuffle: bool,
+    browse: Option<PathBuf>,
+    options: song::LoadOptions,
+) {
+    let mut window_width = 1280.;
+    let mut window_height = 720.;
+
+    request_new_screen_size(window_width, window_height);
+
+    macroquad::rand::srand(macroquad::miniquad::date::now() as u64);
+    if shuffle {
+        playlist.set_shuffle(true);
+    }
 
     let (mut all_keys, key_map) = piano::generate_piano_keys();
 
     let mut piano_props;
-    let mut note_blocks: Vec<Vec<note::NoteBlock>> = note::get_note_blocks(&nbs_file);
 
-    let note_texture = note::load_note_texture();
-    note_texture.set_filter(macroquad::texture::FilterMode::Nearest);
+    let mut audio_engine: audio::AudioEngine = audio::AudioEngine::new(0.5);
+    let mut status_message: Option<StatusMessage> = None;
 
-    let mut audio_engine: audio::AudioEngine = audio::AudioEngine::new(Some(extra_sounds), 0.5);
+    let mut loaded = LoadedSong::new(
+        load_playlist_song(&mut playlist, &options, &mut status_message),
+        &mut audio_engine,
+    );
 
     let mut current_tick: f32; // Current tick in the song (now a float for sub-ticks)
-    let mut elapsed_time: f32 = 0.; // Elapsed time in seconds
+    let (song_length, notes_per_second) = loaded.timing();
+    let mut playback = playback::Playback::new(song_length, notes_per_second);
+    // Whether the end of the song was already sent to the page
+    let mut end_reported = false;
 
     let mut note_dim;
     let mut key_spacing; // Spacing between keys
 
-    let mut played_ticks: Vec<bool> = vec![false; nbs_file.header.song_length as usize];
-
     let instrument_colors = note::generate_instrument_palette();
 
-    let mut is_paused: bool = true;
+    let mut show_info: bool = false;
+
+    // Audio/visual latency compensation in milliseconds, positive when audio lags the visuals
+    let mut latency_ms: f32 = 0.;
+    let mut calibration: Option<calibration::Calibration> = None;
+
+    // Folder of the song browser, the given one or the folder of the first song
+    let browse_dir = browse.clone().unwrap_or_else(|| {
+        playlist
+            .current()
+            .and_then(|path| path.parent())
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+            .to_path_buf()
+    });
+    let mut song_browser: Option<browser::SongBrowser> =
+        browse.map(|_| browser::SongBrowser::new(browse_dir.clone()));
 
     let font_data = include_bytes!("../assets/fonts/Monocraft.ttf");
     let mut font = load_ttf_font_from_bytes(font_data).unwrap();
     font.set_filter(macroquad::texture::FilterMode::Nearest);
     crate::font::FONT.set(font.clone()).unwrap();
+    let mut screen = canvas::ScreenCanvas::new(font.clone());
 
     window_width = window::screen_width();
     window_height = window::screen_height();
@@ -83,41 +314,264 @@ async fn main() {
         }
 
         let delta_time = get_frame_time();
+        audio_engine.update(delta_time);
+
+        if let Some(message) = status_message.as_mut() {
+            message.remaining -= delta_time;
+            if message.remaining <= 0. {
+                status_message = None;
+            }
+        }
 
+        // Replace the song with the first dropped one that loads, the dropped songs becoming
+        // the playlist
+        let dropped_files = get_dropped_files();
+        let dropped_paths: Vec<PathBuf> = dropped_files
+            .iter()
+            .filter_map(|file| file.path.clone())
+            .filter(|path| {
+                path.is_file() && playlist::has_extension(path, &playlist::SONG_EXTENSIONS)
+            })
+            .collect();
+        // Songs sent by the page embedding the web build are loaded like dropped ones
+        let page_song = web::take_pending_song().map(|song| DroppedFile {
+            path: Some(PathBuf::from(song.name)),
+            bytes: Some(song.bytes),
+        });
+        let mut drop_error = None;
+        for file in dropped_files.into_iter().chain(page_song) {
+            let path = file.path.clone();
+            match load_dropped_song(file, &options) {
+                Ok(song_data) => {
+                    let mut dropped = playlist::Playlist::new(dropped_paths.clone());
+                    if let Some(index) = dropped_paths
+                        .iter()
+                        .position(|dropped_path| Some(dropped_path) == path.as_ref())
+                    {
+                        dropped.select(index);
+                    }
+                    dropped.set_repeat(playlist.repeat());
+                    dropped.set_shuffle(playlist.shuffle());
+                    playlist = dropped;
+
+                    loaded = LoadedSong::new(song_data, &mut audio_engine);
+                    loaded.start(&mut playback);
+                    show_info = false;
+                    song_browser = None;
+                    calibration = None;
+                    status_message = None;
+                    drop_error = None;
+                    break;
+                }
+                Err(error) => {
+                    log::warn!("{}", error);
+                    drop_error.get_or_insert(error);
+                }
+            }
+        }
+        if let Some(error) = drop_error {
+            status_message = Some(StatusMessage::error(error));
+        }
+
+        if let Some(active_calibration) = calibration.as_mut() {
+            clear_background(color::SKYBLUE);
+            match active_calibration.update(&mut audio_engine, delta_time) {
+                calibration::CalibrationState::Running => {
+                    active_calibration.draw(window_width, window_height, &font);
+                }
+                calibration::CalibrationState::Cancelled => calibration = None,
+                calibration::CalibrationState::Finished(measured_ms) => {
+                    log::info!("Calibrated latency: {:.0} ms", measured_ms);
+                    latency_ms = measured_ms.round();
+                    calibration = None;
+                }
+            }
+            window::next_frame().await;
+            continue;
+        }
+
+        if let Some(active_browser) = song_browser.as_mut() {
+            clear_background(color::SKYBLUE);
+            match active_browser.update(window_height) {
+                browser::BrowserAction::Browsing => {
+                    active_browser.draw(window_width, window_height, &font);
+                }
+                browser::BrowserAction::Close => song_browser = None,
+                browser::BrowserAction::Play { songs, index } => {
+                    let mut browsed = playlist::Playlist::new(songs);
+                    browsed.select(index);
+                    browsed.set_repeat(playlist.repeat());
+                    browsed.set_shuffle(playlist.shuffle());
+                    playlist = browsed;
+
+                    loaded = LoadedSong::new(
+                        load_playlist_song(&mut playlist, &options, &mut status_message),
+                        &mut audio_engine,
+                    );
+                    loaded.start(&mut playback);
+                    show_info = false;
+                    song_browser = None;
+                }
+            }
+            draw_status_message(&status_message, window_width, &font);
+            window::next_frame().await;
+            continue;
+        }
+
+        if is_key_pressed(KeyCode::B) {
+            playback.transport.is_paused = true;
+            audio_engine.stop_all();
+            song_browser = Some(browser::SongBrowser::new(browse_dir.clone()));
+        }
+
+        if is_key_pressed(KeyCode::C) {
+            playback.transport.is_paused = true;
+            audio_engine.stop_all();
+            calibration = Some(calibration::Calibration::new());
+        }
+        if is_key_pressed(KeyCode::LeftBracket) {
+            latency_ms -= 5.;
+        }
+        if is_key_pressed(KeyCode::RightBracket) {
+            latency_ms += 5.;
+        }
+
+        // Toggle NBS-style cutting of the previous note on the same key and instrument
+        if is_key_pressed(KeyCode::X) {
+            audio_engine.set_cut_previous(!audio_engine.cut_previous());
+        }
+        // Toggle fading out instruments with long samples
+        if is_key_pressed(KeyCode::F) {
+            let envelope = if audio_engine.has_envelopes() {
+                None
+            } else {
+                Some(audio::InstrumentEnvelope {
+                    max_duration: Some(SUSTAIN_MAX_DURATION),
+                    release: SUSTAIN_RELEASE,
+                })
+            };
+            for instrument in audio_engine.long_sample_instruments(SUSTAIN_MAX_DURATION) {
+                audio_engine.set_envelope(instrument, envelope);
+            }
+        }
+
+        // Toggle pre-rendered high quality pitching
+        if is_key_pressed(KeyCode::Q) {
+            match audio_engine.quality() {
+                resample::ResampleQuality::Fast => {
+                    audio_engine.set_quality(resample::ResampleQuality::HighQuality);
+                    audio_engine.prepare_notes(&loaded.note_blocks);
+                }
+                resample::ResampleQuality::HighQuality => {
+                    audio_engine.set_quality(resample::ResampleQuality::Fast);
+                }
+            }
+        }
+
+        if is_key_pressed(KeyCode::I) {
+            show_info = !show_info;
+        }
+
+        if is_key_pressed(KeyCode::S) {
+            playlist.set_shuffle(!playlist.shuffle());
+        }
+        if is_key_pressed(KeyCode::R) {
+            playlist.cycle_repeat();
+        }
+
+        // The keyboard drives the same transport commands as the page embedding the web build
+        let mut commands = web::take_commands();
         if is_key_pressed(KeyCode::Space) || is_mouse_button_pressed(MouseButton::Left) {
-            if elapsed_time >= total_duration {
-                elapsed_time = 0.;
-                played_ticks = vec![false; nbs_file.header.song_length as usize];
-                note_blocks = note::get_note_blocks(&nbs_file);
-                is_paused = false;
+            commands.push(TransportCommand::TogglePause);
+        }
+        if is_key_pressed(KeyCode::N) {
+            commands.push(TransportCommand::Next);
+        }
+        if is_key_pressed(KeyCode::P) {
+            commands.push(TransportCommand::Previous);
+        }
+        if is_key_pressed(KeyCode::Left) {
+            commands.push(TransportCommand::SeekBy(-SEEK_STEP));
+        }
+        if is_key_pressed(KeyCode::Right) {
+            commands.push(TransportCommand::SeekBy(SEEK_STEP));
+        }
+
+        let mut change_song = false;
+        for command in commands {
+            match command {
+                TransportCommand::SetVolume(volume) => audio_engine.set_volume(volume),
+                // Move through the playlist, previous restarting the song when there is none
+                TransportCommand::Next => change_song |= playlist.next(),
+                TransportCommand::Previous => {
+                    if playlist.previous() {
+                        change_song = true;
+                    } else {
+                        playback.restart(&mut audio_engine);
+                        loaded.reset_notes();
+                    }
+                }
+                _ => {
+                    if playback.apply(command, &mut audio_engine) {
+                        loaded.reset_notes();
+                    }
+                }
             }
-            is_paused = !is_paused;
+        }
+        // Continue with the next song once this one is over
+        if !playback.transport.is_paused && playback.is_end() {
+            change_song = playlist.song_finished();
+        }
+        if change_song {
+            audio_engine.stop_all();
+            loaded = LoadedSong::new(
+                load_playlist_song(&mut playlist, &options, &mut status_message),
+                &mut audio_engine,
+            );
+            loaded.start(&mut playback);
+            show_info = false;
         }
 
-        // Update elapsed time if not paused ad song is not finished
-        if !is_paused && elapsed_time < total_duration {
-            elapsed_time += delta_time;
+        // Play the notes reached during this frame, each once
+        for tick in playback.advance(delta_time, &loaded.note_blocks, &mut audio_engine) {
+            web::emit_tick(tick as u32);
         }
+
+        let is_end = playback.is_end();
+        if is_end && !end_reported {
+            web::emit_end();
+        }
+        end_reported = is_end;
+        web::publish_status(web::PlayerStatus {
+            position: playback.transport.elapsed_time,
+            duration: playback.duration(),
+            tick: playback.current_tick(),
+            paused: playback.transport.is_paused,
+            speed: playback.transport.speed,
+            volume: audio_engine.volume(),
+        });
+
         clear_background(color::SKYBLUE);
 
-        current_tick = elapsed_time * notes_per_second;
+        let notes_per_second = playback.notes_per_second();
+        let total_duration = playback.duration();
+        current_tick = playback.current_tick();
+
+        // Tick shown on screen, shifted so the visuals line up with what is heard
+        let visual_tick = current_tick - calibration::latency_ticks(latency_ms, notes_per_second);
 
         // Reset all key press states
         for key in &mut all_keys {
             key.is_pressed = false;
         }
 
-        // get current tick notes to play
-        if let Some(notes) = note_blocks.get((current_tick as f32).floor() as usize) {
-            // if tick notes are not played, play them
-            if !played_ticks[(current_tick as f32).floor() as usize] {
-                audio_engine.play_tick(notes);
-                played_ticks[(current_tick as f32).floor() as usize] = true;
-            }
-        }
-
-        // Trigger piano key presses for current and trigger audio
-        if let Some(notes) = note_blocks.get_mut(current_tick as usize) {
+        // Trigger piano key presses for the visual tick
+        let pressed_notes = if visual_tick >= 0. {
+            loaded.note_blocks.get_mut(visual_tick as usize)
+        } else {
+            None
+        };
+        if let Some(notes) = pressed_notes {
             for note in notes {
                 if note.was_played == false {
                     if let Some(&key_index) = key_map.get(&note.key) {
@@ -130,14 +584,14 @@ async fn main() {
 
         // Draw notes
         let notes_rendered = note::draw_notes(
+            &mut screen,
             window_width,
             window_height,
             &all_keys,
             &key_map,
-            &note_blocks,
+            &loaded.note_blocks,
             &piano_props,
-            &note_texture,
-            current_tick,
+            visual_tick,
             note_dim,
             key_spacing,
             &instrument_colors,
@@ -145,7 +599,13 @@ async fn main() {
 
         // Update and draw piano keys
         piano::update_key_animation(&mut all_keys, delta_time);
-        piano::draw_piano_keys(window_width, window_height, &all_keys, &piano_props);
+        piano::draw_piano_keys(
+            &mut screen,
+            window_width,
+            window_height,
+            &all_keys,
+            &piano_props,
+        );
 
         // Calculate font size based on screen width with min and max limits
         let min_font_size = 20;
@@ -165,11 +625,55 @@ async fn main() {
         let fps = get_fps();
         let current_tick_text = format!("Current Tick: {:.4}", current_tick);
         let notes_rendered_text = format!("Notes Rendered: {}", notes_rendered);
+        let latency_text = format!(
+            "Latency: {:+.0} ms ([ ] to adjust, C to calibrate)",
+            latency_ms
+        );
+        let sustain_text = format!(
+            "Sustain: {}{} (F fade, X cut)",
+            if audio_engine.has_envelopes() {
+                format!("long samples faded after {:.1}s", SUSTAIN_MAX_DURATION)
+            } else {
+                "full samples".to_string()
+            },
+            if audio_engine.cut_previous() {
+                ", cut previous"
+            } else {
+                ""
+            }
+        );
+        let resampling_text = format!(
+            "Resampling: {}{} (Q to toggle)",
+            match audio_engine.quality() {
+                resample::ResampleQuality::Fast => "fast",
+                resample::ResampleQuality::HighQuality => "high quality",
+            },
+            match audio_engine.pending_variants() {
+                0 => String::new(),
+                pending => format!(", rendering {} pitches", pending),
+            }
+        );
+        let playlist_text = if playlist.len() > 1 {
+            format!(
+                "Playlist: {}/{}, shuffle {}, repeat {} (N/P, S, R)",
+                playlist.position() + 1,
+                playlist.len(),
+                if playlist.shuffle() { "on" } else { "off" },
+                playlist.repeat().name()
+            )
+        } else {
+            format!("Repeat: {} (R to change)", playlist.repeat().name())
+        };
         let duration_text = format!(
             "Duration: {}|{}",
-            time_formatter(elapsed_time),
+            time_formatter(playback.transport.elapsed_time),
             time_formatter(total_duration)
         );
+        let speed_text = format!(
+            "Speed: {:.2}x, volume {:.0}% (arrows to seek)",
+            playback.transport.speed,
+            audio_engine.volume() * 100.
+        );
 
         let text_parameters = TextParams {
             font_size,
@@ -180,12 +684,29 @@ async fn main() {
         };
 
         // Draw title
-        draw_text_ex(&title, start_x, start_y, text_parameters.clone());
+        draw_text_ex(&loaded.title, start_x, start_y, text_parameters.clone());
 
         // Draw duration
         start_y += line_height;
         draw_text_ex(&duration_text, start_x, start_y, text_parameters.clone());
 
+        // Draw playback speed and volume
+        start_y += line_height;
+        draw_text_ex(&speed_text, start_x, start_y, text_parameters.clone());
+
+        // Draw song format
+        start_y += line_height;
+        draw_text_ex(
+            &loaded.format_text,
+            start_x,
+            start_y,
+            text_parameters.clone(),
+        );
+
+        // Draw playlist position and modes
+        start_y += line_height;
+        draw_text_ex(&playlist_text, start_x, start_y, text_parameters.clone());
+
         // Draw notes rendered
         start_y += line_height;
         draw_text_ex(
@@ -204,6 +725,18 @@ async fn main() {
             text_parameters.clone(),
         );
 
+        // Draw latency offset
+        start_y += line_height;
+        draw_text_ex(&latency_text, start_x, start_y, text_parameters.clone());
+
+        // Draw sustain mode
+        start_y += line_height;
+        draw_text_ex(&sustain_text, start_x, start_y, text_parameters.clone());
+
+        // Draw resampling quality
+        start_y += line_height;
+        draw_text_ex(&resampling_text, start_x, start_y, text_parameters.clone());
+
         // Draw FPS in the top-right corner
         let fps_text = format!("FPS: {:.2}", fps);
         let fps_text_width = measure_text(&fps_text, Some(&font), font_size, 1.0).width;
@@ -214,10 +747,8 @@ async fn main() {
             text_parameters.clone(),
         );
 
-        let is_end = elapsed_time >= total_duration;
-
         // Draw pause state
-        if is_paused && !is_end {
+        if playback.transport.is_paused && !is_end {
             draw_text_ex(
                 "Paused",
                 window_width / 2. - 50.,
@@ -245,7 +776,7 @@ async fn main() {
             );
             // draw title
             draw_text_ex(
-                &title,
+                &loaded.title,
                 window_width / 2. - 50.,
                 window_height / 2. + 50.,
                 TextParams {
@@ -270,6 +801,12 @@ async fn main() {
             );
         }
 
+        if show_info {
+            loaded.info_overlay.draw(window_width, window_height, &font);
+        }
+
+        draw_status_message(&status_message, window_width, &font);
+
         window::next_frame().await
     }
 }
@@ -0,0 +1,1076 @@
+use std::collections::{BTreeMap, HashMap};
+
+use midly::{
+    Format, Header, MetaMessage, MidiMessage, PitchBend, Smf, Timing, TrackEvent, TrackEventKind,
+    num::{u4, u7, u14, u15, u24, u28},
+};
+use nbs_rs::{NbsFile, NbsHeader, NbsLayer, NbsNote};
+
+use crate::{
+    format, minecraft,
+    sink::{NoteEvent, NoteSink},
+    utils::decode_text,
+    writer,
+};
+
+/// MIDI ticks per quarter note in exported files
+const TICKS_PER_BEAT: u16 = 96;
+/// NBS ticks per quarter note, Note Block Studio shows 4 ticks per beat
+const NBS_TICKS_PER_BEAT: u16 = 4;
+/// MIDI note number of NBS key 0 (A0)
+const KEY_OFFSET: i32 = 21;
+/// General MIDI percussion channel (channel 10)
+const DRUM_CHANNEL: u8 = 9;
+/// Pitch bend range assumed by General MIDI synths, in cents
+const BEND_RANGE_CENTS: f32 = 200.0;
+const CC_PAN: u8 = 10;
+/// NBS key used for percussion notes
+const DRUM_KEY: u8 = 45;
+/// Tempo assumed until the first tempo event, in microseconds per beat (120 BPM)
+const DEFAULT_MICROS_PER_BEAT: u32 = 500_000;
+
+/// What an NBS instrument sounds like in General MIDI
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GmSound {
+    /// Melodic program number (0-127)
+    Program(u8),
+    /// Percussion note on channel 10
+    Drum(u8),
+}
+
+/// Maps NBS instrument ids to General MIDI sounds
+#[derive(Clone, Debug)]
+pub struct GmProgramTable {
+    sounds: HashMap<u8, GmSound>,
+}
+
+impl Default for GmProgramTable {
+    fn default() -> Self {
+        let sounds = [
+            (0, GmSound::Program(46)),   // harp -> orchestral harp
+            (1, GmSound::Program(32)),   // bass -> acoustic bass
+            (2, GmSound::Drum(36)),      // bd -> bass drum 1
+            (3, GmSound::Drum(38)),      // snare -> acoustic snare
+            (4, GmSound::Drum(42)),      // hat -> closed hi-hat
+            (5, GmSound::Program(24)),   // guitar -> nylon guitar
+            (6, GmSound::Program(73)),   // flute -> flute
+            (7, GmSound::Program(14)),   // bell -> tubular bells
+            (8, GmSound::Program(112)),  // chime -> tinkle bell
+            (9, GmSound::Program(13)),   // xylophone -> xylophone
+            (10, GmSound::Program(11)),  // iron xylophone -> vibraphone
+            (11, GmSound::Drum(56)),     // cow bell -> cowbell
+            (12, GmSound::Program(109)), // didgeridoo -> bagpipe
+            (13, GmSound::Program(80)),  // bit -> square lead
+            (14, GmSound::Program(105)), // banjo -> banjo
+            (15, GmSound::Program(4)),   // pling -> electric piano
+        ];
+
+        Self {
+            sounds: sounds.into_iter().collect(),
+        }
+    }
+}
+
+impl GmProgramTable {
+    /// Parses a table with one `<instrument> <program>` or `<instrument> drum:<note>` per line,
+    /// entries not listed keep their default mapping
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut table = Self::default();
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let error = || format!("Invalid program table entry on line {}", line_number + 1);
+            let mut parts = line.split_whitespace();
+            let (Some(instrument), Some(sound), None) = (parts.next(), parts.next(), parts.next())
+            else {
+                return Err(error());
+            };
+
+            let instrument: u8 = instrument.parse().map_err(|_| error())?;
+            let sound = match sound.strip_prefix("drum:") {
+                Some(note) => GmSound::Drum(note.parse().map_err(|_| error())?),
+                None => GmSound::Program(sound.parse().map_err(|_| error())?),
+            };
+            match sound {
+                GmSound::Program(value) | GmSound::Drum(value) if value > 127 => {
+                    return Err(error());
+                }
+                _ => {}
+            }
+
+            table.sounds.insert(instrument, sound);
+        }
+
+        Ok(table)
+    }
+
+    /// Sound for an instrument, custom instruments fall back to the acoustic grand piano
+    pub fn sound(&self, instrument: u8) -> GmSound {
+        self.sounds
+            .get(&instrument)
+            .copied()
+            .unwrap_or(GmSound::Program(0))
+    }
+}
+
+/// Absolute-time event, sorted so note offs come before controls and note ons on the same tick
+#[derive(Clone)]
+struct TimedEvent {
+    time: u32,
+    order: u8,
+    channel: u8,
+    message: MidiMessage,
+}
+
+/// Last values sent on a channel, to avoid repeating controller messages
+#[derive(Clone, Copy, Default, PartialEq)]
+struct ChannelState {
+    program: Option<u8>,
+    pan: Option<u8>,
+    bend: Option<u16>,
+}
+
+/// Hands out the 16 MIDI channels to notes by their program, pan and pitch bend, so notes
+/// sounding together keep their own settings
+#[derive(Default)]
+struct ChannelAllocator {
+    channels: [ChannelState; 16],
+    /// MIDI tick at which the last note of each channel ends
+    busy_until: [u32; 16],
+    /// Notes that had to change the settings of a channel while another note was sounding
+    clashes: usize,
+}
+
+impl ChannelAllocator {
+    /// Picks a channel for a note from `time` to `end`, preferring one already set up for it,
+    /// then the free one idle the longest
+    fn melodic_channel(&mut self, wanted: ChannelState, time: u32, end: u32) -> u8 {
+        let melodic = (0..16u8).filter(|&channel| channel != DRUM_CHANNEL);
+        let channel = match melodic
+            .clone()
+            .find(|&channel| self.channels[channel as usize] == wanted)
+        {
+            Some(channel) => channel,
+            None => {
+                let channel = melodic
+                    .min_by_key(|&channel| self.busy_until[channel as usize])
+                    .unwrap();
+                if self.busy_until[channel as usize] > time {
+                    self.clashes += 1;
+                }
+                channel
+            }
+        };
+        self.busy_until[channel as usize] = self.busy_until[channel as usize].max(end);
+        channel
+    }
+
+    /// Percussion always uses channel 10, a pan change while a drum sounds is a clash
+    fn drum_channel(&mut self, pan: u8, time: u32, end: u32) -> u8 {
+        let index = DRUM_CHANNEL as usize;
+        if self.channels[index].pan.is_some_and(|last| last != pan) && self.busy_until[index] > time
+        {
+            self.clashes += 1;
+        }
+        self.busy_until[index] = self.busy_until[index].max(end);
+        DRUM_CHANNEL
+    }
+}
+
+/// What couldn't be kept when converting a song to MIDI
+#[derive(Debug, Default)]
+pub struct MidiReport {
+    pub notes: usize,
+    /// Notes that changed the program, pan or pitch bend of a channel another note was still
+    /// playing on, because all 16 channels were in use
+    pub channel_clashes: usize,
+}
+
+impl MidiReport {
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.channel_clashes > 0 {
+            warnings.push(format!(
+                "{} notes ran out of MIDI channels and changed the sound of notes still playing",
+                self.channel_clashes
+            ));
+        }
+        warnings
+    }
+}
+
+/// Converts an NBS panning value (0-200, 100 is center) pair to a CC10 value
+fn pan_value(note_panning: u8, layer_panning: u8) -> u8 {
+    let pan = (note_panning as i32 - 100) + (layer_panning as i32 - 100);
+    ((pan.clamp(-100, 100) + 100) * 127 / 200) as u8
+}
+
+/// Splits a pitch in cents into whole semitones and a 14-bit pitch bend value
+fn split_pitch(pitch: i16) -> (i32, u16) {
+    let semitones = (pitch as f32 / 100.0).round() as i32;
+    let cents = pitch as f32 - semitones as f32 * 100.0;
+    let bend = 8192.0 + cents / BEND_RANGE_CENTS * 8192.0;
+    (semitones, bend.round().clamp(0.0, 16383.0) as u16)
+}
+
+/// Converts a song to a Standard MIDI File with one track per layer
+pub fn export_midi(song: &NbsFile, programs: &GmProgramTable) -> (Vec<u8>, MidiReport) {
+    let ticks_per_nbs_tick = (TICKS_PER_BEAT / NBS_TICKS_PER_BEAT) as u32;
+    let notes_per_second = (song.header.tempo as f32 / 100.0).max(0.01);
+    let micros_per_beat = (NBS_TICKS_PER_BEAT as f32 / notes_per_second * 1_000_000.0) as u32;
+
+    let song_name = decode_text(&song.header.song_name);
+    let layer_count = song
+        .notes
+        .iter()
+        .map(|note| note.layer as usize + 1)
+        .max()
+        .unwrap_or(0)
+        .max(song.layers.len());
+
+    // Tempo track
+    let mut tracks = vec![vec![
+        TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::TrackName(song_name.as_bytes())),
+        },
+        TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::new(
+                micros_per_beat.min(0xFF_FFFF),
+            ))),
+        },
+        TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::TimeSignature(
+                song.header.time_signature.max(1),
+                2,
+                24,
+                8,
+            )),
+        },
+        TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        },
+    ]];
+
+    let layer_names: Vec<String> = (0..layer_count)
+        .map(|index| match song.layers.get(index) {
+            Some(layer) if !layer.name.is_empty() => decode_text(&layer.name),
+            _ => format!("Layer {}", index + 1),
+        })
+        .collect();
+
+    let mut layer_events: Vec<Vec<TimedEvent>> = (0..layer_count).map(|_| Vec::new()).collect();
+    let mut channels = ChannelAllocator::default();
+
+    // Channels are handed out in time order
+    let mut notes: Vec<_> = song.notes.iter().collect();
+    notes.sort_by_key(|note| (note.tick, note.layer));
+
+    for note in notes {
+        let layer_index = note.layer as usize;
+        let (layer_volume, layer_panning) = match song.layers.get(layer_index) {
+            Some(layer) => (layer.volume as u32, layer.panning as u8),
+            None => (100, 100),
+        };
+
+        push_note_events(
+            &mut layer_events[layer_index],
+            &mut channels,
+            programs,
+            MidiNote {
+                time: note.tick as u32 * ticks_per_nbs_tick,
+                length: ticks_per_nbs_tick,
+                instrument: note.instrument,
+                key: note.key,
+                velocity: (note.velocity as u32 * layer_volume * 127 / 10_000).clamp(1, 127) as u8,
+                pan: pan_value(note.panning as u8, layer_panning),
+                pitch: note.pitch,
+            },
+        );
+    }
+
+    for (events, name) in layer_events.iter_mut().zip(&layer_names) {
+        tracks.push(track_events(events, name));
+    }
+
+    let report = MidiReport {
+        notes: song.notes.len(),
+        channel_clashes: channels.clashes,
+    };
+    (write_smf(Format::Parallel, tracks), report)
+}
+
+/// A note to convert to MIDI events, timed in MIDI ticks
+struct MidiNote {
+    time: u32,
+    length: u32,
+    instrument: u8,
+    key: u8,
+    /// MIDI velocity (1-127)
+    velocity: u8,
+    /// CC10 value
+    pan: u8,
+    pitch: i16,
+}
+
+/// Adds the events of a note, with the program, pan and pitch bend changes it needs on its
+/// channel
+fn push_note_events(
+    events: &mut Vec<TimedEvent>,
+    channels: &mut ChannelAllocator,
+    programs: &GmProgramTable,
+    note: MidiNote,
+) {
+    let time = note.time;
+    let end = time + note.length;
+    let (semitones, bend) = split_pitch(note.pitch);
+
+    let (channel, key) = match programs.sound(note.instrument) {
+        GmSound::Drum(key) => (channels.drum_channel(note.pan, time, end), key),
+        GmSound::Program(program) => {
+            let wanted = ChannelState {
+                program: Some(program),
+                pan: Some(note.pan),
+                bend: Some(bend),
+            };
+            let channel = channels.melodic_channel(wanted, time, end);
+            let state = &mut channels.channels[channel as usize];
+            if state.program != Some(program) {
+                state.program = Some(program);
+                events.push(TimedEvent {
+                    time,
+                    order: 1,
+                    channel,
+                    message: MidiMessage::ProgramChange {
+                        program: u7::new(program),
+                    },
+                });
+            }
+            let key = (note.key as i32 + KEY_OFFSET + semitones).clamp(0, 127) as u8;
+            (channel, key)
+        }
+    };
+
+    let state = &mut channels.channels[channel as usize];
+    if state.pan != Some(note.pan) {
+        state.pan = Some(note.pan);
+        events.push(TimedEvent {
+            time,
+            order: 1,
+            channel,
+            message: MidiMessage::Controller {
+                controller: u7::new(CC_PAN),
+                value: u7::new(note.pan),
+            },
+        });
+    }
+    if channel != DRUM_CHANNEL && state.bend != Some(bend) {
+        state.bend = Some(bend);
+        events.push(TimedEvent {
+            time,
+            order: 1,
+            channel,
+            message: MidiMessage::PitchBend {
+                bend: PitchBend(u14::new(bend)),
+            },
+        });
+    }
+
+    events.push(TimedEvent {
+        time,
+        order: 2,
+        channel,
+        message: MidiMessage::NoteOn {
+            key: u7::new(key),
+            vel: u7::new(note.velocity),
+        },
+    });
+    events.push(TimedEvent {
+        time: end,
+        order: 0,
+        channel,
+        message: MidiMessage::NoteOff {
+            key: u7::new(key),
+            vel: u7::new(0),
+        },
+    });
+}
+
+/// Sorts the events of a track and converts them to delta-timed track events
+fn track_events<'a>(events: &mut [TimedEvent], name: &'a str) -> Vec<TrackEvent<'a>> {
+    events.sort_by_key(|event| (event.time, event.order));
+
+    let mut track = vec![TrackEvent {
+        delta: u28::new(0),
+        kind: TrackEventKind::Meta(MetaMessage::TrackName(name.as_bytes())),
+    }];
+
+    let mut last_time = 0;
+    for event in events.iter() {
+        track.push(TrackEvent {
+            delta: u28::new(event.time - last_time),
+            kind: TrackEventKind::Midi {
+                channel: u4::new(event.channel),
+                message: event.message,
+            },
+        });
+        last_time = event.time;
+    }
+
+    track.push(TrackEvent {
+        delta: u28::new(0),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+    track
+}
+
+fn write_smf(format: Format, tracks: Vec<Vec<TrackEvent>>) -> Vec<u8> {
+    let smf = Smf {
+        header: Header::new(format, Timing::Metrical(u15::new(TICKS_PER_BEAT))),
+        tracks,
+    };
+
+    let mut bytes = Vec::new();
+    smf.write_std(&mut bytes)
+        .expect("Writing to a Vec can't fail");
+    bytes
+}
+
+/// Length of the notes written by `MidiSink`, in MIDI ticks
+const SINK_NOTE_LENGTH: u32 = (TICKS_PER_BEAT / NBS_TICKS_PER_BEAT) as u32;
+
+/// Writes the triggered notes to a Standard MIDI File at 120 BPM, e.g. to record what the
+/// player played
+pub struct MidiSink {
+    programs: GmProgramTable,
+    events: Vec<TimedEvent>,
+    channels: ChannelAllocator,
+}
+
+impl MidiSink {
+    pub fn new(programs: GmProgramTable) -> Self {
+        Self {
+            programs,
+            events: Vec::new(),
+            channels: ChannelAllocator::default(),
+        }
+    }
+
+    /// Single track MIDI file of the notes triggered so far
+    pub fn to_smf(&self) -> Vec<u8> {
+        let mut events = self.events.clone();
+        write_smf(
+            Format::SingleTrack,
+            vec![track_events(&mut events, "Recording")],
+        )
+    }
+}
+
+/// Channels are shared by notes with the same sound, the time of the notes is kept as is
+impl NoteSink for MidiSink {
+    fn trigger_note(&mut self, note: &NoteEvent) {
+        let seconds_per_tick = DEFAULT_MICROS_PER_BEAT as f64 / 1_000_000. / TICKS_PER_BEAT as f64;
+        push_note_events(
+            &mut self.events,
+            &mut self.channels,
+            &self.programs,
+            MidiNote {
+                time: (note.time.max(0.) / seconds_per_tick).round() as u32,
+                length: SINK_NOTE_LENGTH,
+                instrument: note.instrument,
+                key: note.key,
+                velocity: (note.velocity as u32 * 127 / 100).clamp(1, 127) as u8,
+                pan: pan_value(note.panning, 100),
+                pitch: note.pitch,
+            },
+        );
+    }
+}
+
+/// Options used when converting a MIDI file to a note block song
+#[derive(Clone, Copy, Debug)]
+pub struct MidiImportOptions {
+    /// Tempo of the resulting song, MIDI timing is quantized to these ticks
+    pub ticks_per_second: f32,
+}
+
+impl Default for MidiImportOptions {
+    fn default() -> Self {
+        Self {
+            ticks_per_second: 20.0,
+        }
+    }
+}
+
+/// Vanilla instrument closest to a General MIDI program
+fn program_instrument(program: u8) -> u8 {
+    match program {
+        4 | 5 => 15,                        // electric pianos -> pling
+        0..=7 => 0,                         // pianos -> harp
+        8 | 9 | 14 => 7,                    // celesta, glockenspiel, tubular bells -> bell
+        10 => 8,                            // music box -> chime
+        11 => 10,                           // vibraphone -> iron xylophone
+        12 | 13 => 9,                       // marimba, xylophone -> xylophone
+        15 => 14,                           // dulcimer -> banjo
+        24..=31 => 5,                       // guitars -> guitar
+        32..=39 => 1,                       // basses -> bass
+        46 => 0,                            // orchestral harp -> harp
+        47 => 2,                            // timpani -> bass drum
+        80..=87 => 13,                      // synth leads -> bit
+        88..=103 => 15,                     // synth pads and effects -> pling
+        104..=107 => 14,                    // sitar, banjo, shamisen, koto -> banjo
+        108 => 7,                           // kalimba -> bell
+        109 => 12,                          // bagpipe -> didgeridoo
+        112 => 8,                           // tinkle bell -> chime
+        113 => 11,                          // agogo -> cow bell
+        114 => 10,                          // steel drums -> iron xylophone
+        115 => 4,                           // woodblock -> hat
+        116 | 117 => 2,                     // taiko, melodic tom -> bass drum
+        118 | 119 => 3,                     // synth drum, reverse cymbal -> snare
+        16..=23 | 40..=79 | 110 | 111 => 6, // organs, strings, brass, reeds, pipes -> flute
+        _ => 0,
+    }
+}
+
+/// Vanilla percussion instrument for a General MIDI drum note
+fn drum_instrument(key: u8) -> u8 {
+    match key {
+        35 | 36 | 41 | 43 | 45 | 47 => 2, // bass drums, low toms -> bd
+        37..=40 | 48 | 50 => 3,           // snares, claps, high toms -> snare
+        _ => 4,                           // hi-hats, cymbals and the rest -> hat
+    }
+}
+
+/// Converts MIDI ticks to seconds following the tempo changes of the file
+struct TempoMap {
+    timing: Timing,
+    /// (MIDI tick, seconds at that tick, microseconds per beat from then on)
+    changes: Vec<(u64, f64, u32)>,
+}
+
+impl TempoMap {
+    fn new(timing: Timing, mut tempos: Vec<(u64, u32)>) -> Self {
+        tempos.sort_by_key(|(tick, _)| *tick);
+
+        let mut changes = vec![(0, 0.0, DEFAULT_MICROS_PER_BEAT)];
+        let ticks_per_beat = match timing {
+            Timing::Metrical(ticks) => ticks.as_int().max(1) as f64,
+            Timing::Timecode(..) => 1.0,
+        };
+        for (tick, micros_per_beat) in tempos {
+            let &(last_tick, last_seconds, last_tempo) = changes.last().unwrap();
+            let seconds =
+                last_seconds + (tick - last_tick) as f64 / ticks_per_beat * last_tempo as f64 / 1e6;
+            changes.push((tick, seconds, micros_per_beat));
+        }
+
+        Self { timing, changes }
+    }
+
+    fn seconds(&self, tick: u64) -> f64 {
+        match self.timing {
+            Timing::Timecode(fps, subframes) => {
+                tick as f64 / (fps.as_f32() as f64 * subframes.max(1) as f64)
+            }
+            Timing::Metrical(ticks_per_beat) => {
+                let index = self
+                    .changes
+                    .partition_point(|(change_tick, _, _)| *change_tick <= tick)
+                    .saturating_sub(1);
+                let (change_tick, seconds, micros_per_beat) = self.changes[index];
+                seconds
+                    + (tick - change_tick) as f64 / ticks_per_beat.as_int().max(1) as f64
+                        * micros_per_beat as f64
+                        / 1e6
+            }
+        }
+    }
+}
+
+struct ImportedNote {
+    tick: u32,
+    instrument: u8,
+    key: u8,
+    velocity: u8,
+    panning: u8,
+}
+
+/// Notes of one MIDI track and channel, spread over as many layers as its polyphony needs
+struct ImportedGroup {
+    name: String,
+    ticks: BTreeMap<u32, Vec<ImportedNote>>,
+}
+
+/// What couldn't be kept when converting a MIDI file to a note block song
+#[derive(Debug, Default)]
+pub struct MidiImportReport {
+    pub notes: usize,
+    /// Notes left out because they come after the last tick an NBS file can reach, or after a
+    /// silence longer than a note jump can skip
+    pub truncated: usize,
+}
+
+impl MidiImportReport {
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.truncated > 0 {
+            warnings.push(format!(
+                "{} notes were cut off, the song is too long for an NBS file",
+                self.truncated
+            ));
+        }
+        warnings
+    }
+}
+
+/// Converts a Standard MIDI File to the bytes of an NBS song in the latest version
+pub fn import_midi(
+    bytes: &[u8],
+    options: &MidiImportOptions,
+) -> Result<(Vec<u8>, MidiImportReport), String> {
+    let smf = Smf::parse(bytes).map_err(|e| format!("Invalid MIDI file: {}", e))?;
+    let ticks_per_second = options.ticks_per_second.clamp(0.01, 655.35);
+
+    // Flatten every track to absolute time, so program and pan changes apply across tracks
+    let mut tempos = Vec::new();
+    let mut events = Vec::new();
+    let mut track_names: Vec<Option<String>> = vec![None; smf.tracks.len()];
+    let mut time_signature = 4;
+    for (track_index, track) in smf.tracks.iter().enumerate() {
+        let mut time: u64 = 0;
+        for event in track {
+            time += event.delta.as_int() as u64;
+            match event.kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => {
+                    tempos.push((time, tempo.as_int()))
+                }
+                TrackEventKind::Meta(MetaMessage::TrackName(name)) => {
+                    track_names[track_index] = Some(decode_text(name))
+                }
+                TrackEventKind::Meta(MetaMessage::TimeSignature(numerator, ..)) if time == 0 => {
+                    time_signature = numerator.clamp(2, 8)
+                }
+                TrackEventKind::Midi { channel, message } => {
+                    events.push((time, track_index, channel.as_int(), message))
+                }
+                _ => {}
+            }
+        }
+    }
+    events.sort_by_key(|(time, track_index, _, _)| (*time, *track_index));
+
+    let tempo_map = TempoMap::new(smf.header.timing, tempos);
+    let mut programs = [0u8; 16];
+    let mut pans = [100u8; 16];
+    let mut groups: BTreeMap<(usize, u8), ImportedGroup> = BTreeMap::new();
+
+    for (time, track_index, channel, message) in events {
+        match message {
+            MidiMessage::ProgramChange { program } => programs[channel as usize] = program.as_int(),
+            MidiMessage::Controller { controller, value } if controller.as_int() == CC_PAN => {
+                pans[channel as usize] = (value.as_int() as u32 * 200 / 127) as u8
+            }
+            MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => {
+                let tick = (tempo_map.seconds(time) * ticks_per_second as f64).round() as u32;
+                let (instrument, key) = if channel == DRUM_CHANNEL {
+                    (drum_instrument(key.as_int()), DRUM_KEY)
+                } else {
+                    (
+                        program_instrument(programs[channel as usize]),
+                        minecraft::transpose_into_range(key.as_int() as i32 - KEY_OFFSET),
+                    )
+                };
+
+                let group = groups
+                    .entry((track_index, channel))
+                    .or_insert_with(|| ImportedGroup {
+                        name: match &track_names[track_index] {
+                            Some(name) if !name.is_empty() => {
+                                format!("{} (ch {})", name, channel + 1)
+                            }
+                            _ => format!("Track {} (ch {})", track_index + 1, channel + 1),
+                        },
+                        ticks: BTreeMap::new(),
+                    });
+
+                // Transposing and quantizing can stack identical notes, keep only one
+                let tick_notes = group.ticks.entry(tick).or_default();
+                if !tick_notes
+                    .iter()
+                    .any(|note| note.instrument == instrument && note.key == key)
+                {
+                    tick_notes.push(ImportedNote {
+                        tick,
+                        instrument,
+                        key,
+                        velocity: (vel.as_int() as u32 * 100 / 127) as u8,
+                        panning: pans[channel as usize],
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Expand each group into one layer per simultaneous note
+    let mut layer_names = Vec::new();
+    let mut notes: BTreeMap<u32, Vec<(u16, ImportedNote)>> = BTreeMap::new();
+    for group in groups.into_values() {
+        let first_layer = layer_names.len();
+        let polyphony = group.ticks.values().map(Vec::len).max().unwrap_or(0);
+        for index in 0..polyphony {
+            layer_names.push(if polyphony > 1 {
+                format!("{} #{}", group.name, index + 1)
+            } else {
+                group.name.clone()
+            });
+        }
+
+        for tick_notes in group.ticks.into_values() {
+            for (index, note) in tick_notes.into_iter().enumerate() {
+                notes
+                    .entry(note.tick)
+                    .or_default()
+                    .push(((first_layer + index) as u16, note));
+            }
+        }
+    }
+
+    if layer_names.len() > u16::MAX as usize {
+        return Err("MIDI file needs more layers than an NBS file can hold".to_string());
+    }
+
+    let song_name = track_names.first().cloned().flatten().unwrap_or_default();
+
+    // Ticks are stored as jumps of at most i16::MAX from the previous one, so the song ends at
+    // the first tick that can't be reached
+    let mut report = MidiImportReport::default();
+    let mut song_notes = Vec::new();
+    let mut last_tick: i64 = -1;
+    for (tick, mut tick_notes) in notes {
+        if report.truncated > 0
+            || tick > u16::MAX as u32
+            || tick as i64 - last_tick > i16::MAX as i64
+        {
+            report.truncated += tick_notes.len();
+            continue;
+        }
+        last_tick = tick as i64;
+        report.notes += tick_notes.len();
+
+        tick_notes.sort_by_key(|(layer, _)| *layer);
+        for (layer, note) in tick_notes {
+            song_notes.push(NbsNote {
+                tick: tick as _,
+                layer: layer as _,
+                instrument: note.instrument as _,
+                key: note.key as _,
+                velocity: note.velocity as _,
+                panning: note.panning as _,
+                pitch: 0,
+            });
+        }
+    }
+
+    let song = NbsFile {
+        header: NbsHeader {
+            version: writer::LATEST_VERSION as _,
+            default_instruments: minecraft::INSTRUMENTS.len() as _,
+            song_length: song_notes.last().map_or(0, |note| note.tick as _),
+            song_layers: layer_names.len() as _,
+            song_name: song_name.into_bytes(),
+            song_author: Vec::new(),
+            original_author: Vec::new(),
+            description: Vec::new(),
+            tempo: (ticks_per_second * 100.0).round() as _,
+            auto_save: 0,
+            auto_save_duration: 10,
+            time_signature: time_signature as _,
+            minutes_spent: 0,
+            left_clicks: 0,
+            right_clicks: 0,
+            blocks_added: 0,
+            blocks_removed: 0,
+            song_origin: Vec::new(),
+            loop_on_off: 0,
+            max_loop_count: 0,
+            loop_start_tick: 0,
+        },
+        notes: song_notes,
+        layers: layer_names
+            .into_iter()
+            .map(|name| NbsLayer {
+                name: name.into_bytes(),
+                lock: 0,
+                volume: 100,
+                panning: format::DEFAULT_PANNING as _,
+            })
+            .collect(),
+        instruments: Vec::new(),
+    };
+
+    Ok((writer::write_nbs(&song), report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_program_table_parse() {
+        let table = GmProgramTable::parse("# custom mapping\n0 0\n16 drum:49 # crash\n").unwrap();
+
+        assert_eq!(table.sound(0), GmSound::Program(0));
+        assert_eq!(table.sound(16), GmSound::Drum(49));
+        assert_eq!(table.sound(2), GmSound::Drum(36));
+        assert!(GmProgramTable::parse("0 128").is_err());
+        assert!(GmProgramTable::parse("harp 0").is_err());
+    }
+
+    #[test]
+    fn test_split_pitch() {
+        assert_eq!(split_pitch(0), (0, 8192));
+        assert_eq!(split_pitch(1200), (12, 8192));
+        assert_eq!(split_pitch(-40), (0, 6554));
+        assert_eq!(split_pitch(130), (1, 9421));
+    }
+
+    #[test]
+    fn test_allocator_reuses_free_channels() {
+        let mut channels = ChannelAllocator::default();
+        let harp = ChannelState {
+            program: Some(46),
+            pan: Some(63),
+            bend: Some(8192),
+        };
+
+        let first = channels.melodic_channel(harp, 0, 24);
+        channels.channels[first as usize] = harp;
+        assert_eq!(channels.melodic_channel(harp, 0, 24), first);
+
+        let bass = ChannelState {
+            program: Some(32),
+            ..harp
+        };
+        let second = channels.melodic_channel(bass, 0, 24);
+        assert_ne!(second, first);
+        assert_ne!(second, DRUM_CHANNEL);
+        assert_eq!(channels.clashes, 0);
+    }
+
+    #[test]
+    fn test_midi_round_trip() {
+        let nbs_data = include_bytes!("../test-assets/nyan_cat.nbs") as &[u8];
+        let song = crate::song::load_nbs_file(Some(nbs_data)).song;
+
+        let (midi, _) = export_midi(&song, &GmProgramTable::default());
+        let options = MidiImportOptions {
+            ticks_per_second: song.header.tempo as f32 / 100.0,
+        };
+        let (nbs, report) = import_midi(&midi, &options).unwrap();
+        let imported = nbs_rs::NbsParser::new(&nbs).parse().unwrap();
+
+        // Quantizing back at the original tempo keeps every tick
+        let mut original_ticks: Vec<u32> = song.notes.iter().map(|n| n.tick as u32).collect();
+        let mut imported_ticks: Vec<u32> = imported.notes.iter().map(|n| n.tick as u32).collect();
+        original_ticks.dedup();
+        imported_ticks.dedup();
+        assert_eq!(original_ticks, imported_ticks);
+        assert_eq!(imported.header.tempo, song.header.tempo);
+        assert_eq!(report.notes, imported.notes.len());
+        assert_eq!(report.truncated, 0);
+    }
+
+    #[test]
+    fn test_import_cuts_notes_nbs_cant_reach() {
+        // Notes at 0 seconds, 1000 seconds and 2000 seconds, at 120 BPM a second is 192 ticks
+        let note_on = |delta: u32| TrackEvent {
+            delta: u28::new(delta),
+            kind: TrackEventKind::Midi {
+                channel: u4::new(0),
+                message: MidiMessage::NoteOn {
+                    key: u7::new(66),
+                    vel: u7::new(100),
+                },
+            },
+        };
+        let track = vec![
+            note_on(0),
+            note_on(1000 * 192),
+            note_on(1000 * 192),
+            TrackEvent {
+                delta: u28::new(0),
+                kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+            },
+        ];
+        let midi = write_smf(Format::SingleTrack, vec![track]);
+
+        // At 20 ticks per second the second note is 20000 ticks in, the third 40000
+        let (_, report) = import_midi(&midi, &MidiImportOptions::default()).unwrap();
+        assert_eq!((report.notes, report.truncated), (3, 0));
+
+        // At 40 the jump to the second note is too long, which also cuts off the third
+        let options = MidiImportOptions {
+            ticks_per_second: 40.0,
+        };
+        let (nbs, report) = import_midi(&midi, &options).unwrap();
+        let imported = nbs_rs::NbsParser::new(&nbs).parse().unwrap();
+        assert_eq!((report.notes, report.truncated), (1, 2));
+        assert_eq!(imported.notes.len(), 1);
+        assert_eq!(report.warnings().len(), 1);
+    }
+
+    #[test]
+    fn test_export_midi() {
+        let nbs_data = include_bytes!("../test-assets/nyan_cat.nbs") as &[u8];
+        let song = crate::song::load_nbs_file(Some(nbs_data)).song;
+
+        let (bytes, report) = export_midi(&song, &GmProgramTable::default());
+        let smf = Smf::parse(&bytes).unwrap();
+
+        let note_ons = smf
+            .tracks
+            .iter()
+            .flatten()
+            .filter(|event| {
+                matches!(
+                    event.kind,
+                    TrackEventKind::Midi {
+                        message: MidiMessage::NoteOn { .. },
+                        ..
+                    }
+                )
+            })
+            .count();
+
+        assert!(smf.tracks.len() > song.layers.len());
+        assert_eq!(note_ons, song.notes.len());
+        assert_eq!(report.notes, song.notes.len());
+    }
+
+    /// Song with one note on each of `layers` layers, all on the first tick
+    fn chord_song(layers: usize) -> NbsFile {
+        let nbs_data = include_bytes!("../test-assets/nyan_cat.nbs") as &[u8];
+        let mut song = crate::song::load_nbs_file(Some(nbs_data)).song;
+        song.layers.clear();
+        song.notes.truncate(layers);
+        for (layer, note) in song.notes.iter_mut().enumerate() {
+            note.tick = 0;
+            note.layer = layer as _;
+            note.key = 45;
+            note.panning = 100;
+            note.pitch = 0;
+        }
+        song
+    }
+
+    /// Channel of the note on each track, skipping the tempo track
+    fn note_on_channels(bytes: &[u8]) -> Vec<u8> {
+        let smf = Smf::parse(bytes).unwrap();
+        smf.tracks[1..]
+            .iter()
+            .map(|track| {
+                track
+                    .iter()
+                    .find_map(|event| match event.kind {
+                        TrackEventKind::Midi {
+                            channel,
+                            message: MidiMessage::NoteOn { .. },
+                        } => Some(channel.as_int()),
+                        _ => None,
+                    })
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_export_more_layers_than_channels() {
+        let programs = GmProgramTable::default();
+
+        // Every vanilla instrument on its own layer, then the first four again
+        let mut song = chord_song(20);
+        for (layer, note) in song.notes.iter_mut().enumerate() {
+            note.instrument = (layer % 16) as u8;
+        }
+        let (bytes, report) = export_midi(&song, &programs);
+        let channels = note_on_channels(&bytes);
+
+        assert_eq!(channels.len(), 20);
+        for (layer, note) in song.notes.iter().enumerate() {
+            let channel = channels[layer];
+            match programs.sound(note.instrument) {
+                GmSound::Drum(_) => assert_eq!(channel, DRUM_CHANNEL),
+                GmSound::Program(program) => {
+                    assert_ne!(channel, DRUM_CHANNEL);
+                    // Only notes with the same program share a channel
+                    for (other, other_note) in song.notes.iter().enumerate() {
+                        if channels[other] == channel {
+                            assert_eq!(
+                                programs.sound(other_note.instrument),
+                                GmSound::Program(program)
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        assert_eq!(report.channel_clashes, 0);
+        assert!(report.warnings().is_empty());
+
+        // 20 different pans can't fit in the 15 melodic channels
+        let mut song = chord_song(20);
+        for (layer, note) in song.notes.iter_mut().enumerate() {
+            note.instrument = 0;
+            note.panning = (layer * 10) as _;
+        }
+        let (_, report) = export_midi(&song, &programs);
+
+        assert_eq!(report.channel_clashes, 5);
+        assert_eq!(report.warnings().len(), 1);
+    }
+
+    #[test]
+    fn test_midi_sink_keeps_note_times() {
+        let mut sink = MidiSink::new(GmProgramTable::default());
+        for (time, instrument) in [(0., 0), (0.5, 2)] {
+            sink.trigger_note(&NoteEvent {
+                tick: 0,
+                time,
+                instrument,
+                key: 45,
+                velocity: 100,
+                panning: 100,
+                pitch: 0,
+            });
+        }
+
+        let bytes = sink.to_smf();
+        let smf = Smf::parse(&bytes).unwrap();
+        let mut time = 0;
+        let mut note_ons = Vec::new();
+        for event in &smf.tracks[0] {
+            time += event.delta.as_int();
+            if let TrackEventKind::Midi {
+                channel,
+                message: MidiMessage::NoteOn { key, .. },
+            } = event.kind
+            {
+                note_ons.push((time, channel.as_int(), key.as_int()));
+            }
+        }
+
+        // Half a second is a beat at 120 BPM, the bass drum going to the percussion channel
+        assert_eq!(
+            note_ons,
+            vec![(0, 0, 66), (TICKS_PER_BEAT as u32, DRUM_CHANNEL, 36)]
+        );
+    }
+}
@@ -0,0 +1,173 @@
+/// Lowest NBS key playable by a vanilla note block (F#3)
+pub const MIN_KEY: u8 = 33;
+/// Highest NBS key playable by a vanilla note block (F#5)
+pub const MAX_KEY: u8 = 57;
+/// Game ticks per second
+pub const GAME_TICKS_PER_SECOND: f32 = 20.0;
+
+/// Minecraft versions that exports can target
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GameVersion {
+    V1_13,
+    V1_14,
+    V1_15,
+    V1_16,
+    V1_17,
+    V1_18,
+    V1_19,
+    V1_20,
+    V1_21,
+}
+
+impl GameVersion {
+    pub const LATEST: GameVersion = GameVersion::V1_21;
+
+    /// Parses a version like `1.20` or `1.20.4`, patch versions map to their minor version
+    pub fn parse(text: &str) -> Option<Self> {
+        let minor = text.strip_prefix("1.")?.split('.').next()?;
+        Some(match minor.parse::<u8>().ok()? {
+            13 => GameVersion::V1_13,
+            14 => GameVersion::V1_14,
+            15 => GameVersion::V1_15,
+            16 => GameVersion::V1_16,
+            17 => GameVersion::V1_17,
+            18 => GameVersion::V1_18,
+            19 => GameVersion::V1_19,
+            20 => GameVersion::V1_20,
+            21 => GameVersion::V1_21,
+            _ => return None,
+        })
+    }
+
+    /// Data pack format of the first release of the version
+    pub fn pack_format(self) -> u32 {
+        match self {
+            GameVersion::V1_13 | GameVersion::V1_14 => 4,
+            GameVersion::V1_15 => 5,
+            GameVersion::V1_16 => 6,
+            GameVersion::V1_17 => 7,
+            GameVersion::V1_18 => 8,
+            GameVersion::V1_19 => 10,
+            GameVersion::V1_20 => 15,
+            GameVersion::V1_21 => 48,
+        }
+    }
+
+    /// World data version of the first release of the version
+    pub fn data_version(self) -> i32 {
+        match self {
+            GameVersion::V1_13 => 1519,
+            GameVersion::V1_14 => 1952,
+            GameVersion::V1_15 => 2225,
+            GameVersion::V1_16 => 2566,
+            GameVersion::V1_17 => 2724,
+            GameVersion::V1_18 => 2860,
+            GameVersion::V1_19 => 3105,
+            GameVersion::V1_20 => 3463,
+            GameVersion::V1_21 => 3953,
+        }
+    }
+
+    /// Data pack folder holding functions, renamed to singular in 1.21
+    pub fn function_dir(self) -> &'static str {
+        if self >= GameVersion::V1_21 {
+            "function"
+        } else {
+            "functions"
+        }
+    }
+}
+
+/// A vanilla note block instrument
+pub struct VanillaInstrument {
+    /// Name used by the `instrument` block state and the `block.note_block.*` sounds
+    pub name: &'static str,
+    /// Block placed under the note block to select the instrument
+    pub base_block: &'static str,
+    /// First version with this instrument
+    pub since: GameVersion,
+}
+
+const fn instrument(
+    name: &'static str,
+    base_block: &'static str,
+    since: GameVersion,
+) -> VanillaInstrument {
+    VanillaInstrument {
+        name,
+        base_block,
+        since,
+    }
+}
+
+/// Vanilla instruments in NBS order
+pub const INSTRUMENTS: [VanillaInstrument; 16] = [
+    instrument("harp", "minecraft:dirt", GameVersion::V1_13),
+    instrument("bass", "minecraft:oak_planks", GameVersion::V1_13),
+    instrument("basedrum", "minecraft:stone", GameVersion::V1_13),
+    instrument("snare", "minecraft:sand", GameVersion::V1_13),
+    instrument("hat", "minecraft:glass", GameVersion::V1_13),
+    instrument("guitar", "minecraft:white_wool", GameVersion::V1_13),
+    instrument("flute", "minecraft:clay", GameVersion::V1_13),
+    instrument("bell", "minecraft:gold_block", GameVersion::V1_13),
+    instrument("chime", "minecraft:packed_ice", GameVersion::V1_13),
+    instrument("xylophone", "minecraft:bone_block", GameVersion::V1_13),
+    instrument("iron_xylophone", "minecraft:iron_block", GameVersion::V1_14),
+    instrument("cow_bell", "minecraft:soul_sand", GameVersion::V1_14),
+    instrument("didgeridoo", "minecraft:pumpkin", GameVersion::V1_14),
+    instrument("bit", "minecraft:emerald_block", GameVersion::V1_14),
+    instrument("banjo", "minecraft:hay_block", GameVersion::V1_14),
+    instrument("pling", "minecraft:glowstone", GameVersion::V1_14),
+];
+
+/// Vanilla instrument for an NBS instrument id, if it exists in the given version
+pub fn vanilla_instrument(
+    instrument: u8,
+    version: GameVersion,
+) -> Option<&'static VanillaInstrument> {
+    INSTRUMENTS
+        .get(instrument as usize)
+        .filter(|instrument| instrument.since <= version)
+}
+
+/// Moves a key into the vanilla note block range by whole octaves
+pub fn transpose_into_range(key: i32) -> u8 {
+    let mut key = key;
+    while key < MIN_KEY as i32 {
+        key += 12;
+    }
+    while key > MAX_KEY as i32 {
+        key -= 12;
+    }
+    key as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_game_version() {
+        assert_eq!(GameVersion::parse("1.20.4"), Some(GameVersion::V1_20));
+        assert_eq!(GameVersion::parse("1.13"), Some(GameVersion::V1_13));
+        assert_eq!(GameVersion::parse("1.12"), None);
+        assert_eq!(GameVersion::parse("latest"), None);
+    }
+
+    #[test]
+    fn test_vanilla_instrument_since() {
+        assert!(vanilla_instrument(15, GameVersion::V1_13).is_none());
+        assert_eq!(
+            vanilla_instrument(15, GameVersion::V1_14).unwrap().name,
+            "pling"
+        );
+        assert!(vanilla_instrument(16, GameVersion::LATEST).is_none());
+    }
+
+    #[test]
+    fn test_transpose_into_range() {
+        assert_eq!(transpose_into_range(45), 45); // F#4 is in range
+        assert_eq!(transpose_into_range(0), 36); // A0 goes up 3 octaves
+        assert_eq!(transpose_into_range(87), 51); // C8 goes down 3 octaves
+    }
+}
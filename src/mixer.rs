@@ -0,0 +1,201 @@
+use std::{collections::HashMap, io::Cursor, sync::Arc};
+
+use kira::{Frame, sound::static_sound::StaticSoundData};
+
+use crate::{
+    audio::{self, AudioEngine, VANILLA_SOUND_KEY, VANILLA_SOUNDS},
+    resample::{PitchCache, resample_sinc},
+    sink::{NoteEvent, NoteSink},
+};
+
+/// Sample rate of rendered songs
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+/// Renders triggered notes into a stereo buffer instead of playing them, e.g. to export a song
+/// as audio faster than real time
+pub struct OfflineMixer {
+    sample_rate: u32,
+    /// Frames at the mixer's sample rate and base key of each instrument
+    sounds: HashMap<u32, (Arc<[Frame]>, f64)>,
+    /// Instrument id of the current song's first custom instrument
+    first_custom: u8,
+    pitch_cache: PitchCache,
+    volume: f32,
+    frames: Vec<Frame>,
+}
+
+impl OfflineMixer {
+    pub fn new(sample_rate: u32) -> Result<Self, String> {
+        let mut mixer = Self {
+            sample_rate,
+            sounds: HashMap::new(),
+            first_custom: VANILLA_SOUNDS.len() as u8,
+            pitch_cache: PitchCache::default(),
+            volume: 1.,
+            frames: Vec::new(),
+        };
+        for (i, sound) in VANILLA_SOUNDS.iter().enumerate() {
+            mixer.add_sound(i as u32, sound.to_vec(), VANILLA_SOUND_KEY)?;
+        }
+        Ok(mixer)
+    }
+
+    /// Decodes a sample, converting it to the mixer's sample rate
+    fn add_sound(&mut self, id: u32, data: Vec<u8>, key: f64) -> Result<(), String> {
+        let sound = StaticSoundData::from_cursor(Cursor::new(data))
+            .map_err(|e| format!("Failed to decode the sound of instrument {}: {}", id, e))?;
+        let frames = if sound.sample_rate == self.sample_rate {
+            sound.frames
+        } else {
+            let ratio = sound.sample_rate as f64 / self.sample_rate as f64;
+            resample_sinc(&sound.frames, ratio).into()
+        };
+        self.sounds.insert(id, (frames, key));
+        Ok(())
+    }
+
+    /// Replaces the custom instrument sounds with the sounds of another song, whose custom
+    /// instruments start at `first_custom`
+    pub fn set_extra_sounds(
+        &mut self,
+        first_custom: u8,
+        extra_sounds: &[Option<(Vec<u8>, f64)>],
+    ) -> Result<(), String> {
+        let vanilla_count = VANILLA_SOUNDS.len() as u32;
+        self.sounds.retain(|id, _| *id < vanilla_count);
+        self.pitch_cache.clear();
+
+        for (i, sound) in extra_sounds.iter().enumerate() {
+            if let Some((data, key)) = sound {
+                self.add_sound(vanilla_count + i as u32, data.clone(), *key)?;
+            }
+        }
+        self.first_custom = first_custom;
+        Ok(())
+    }
+
+    /// Sets the note volume from 0 to 1
+    pub fn set_volume(&mut self, volume: f32) {
+        if volume.is_finite() {
+            self.volume = volume.clamp(0., 1.);
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Frames rendered so far, up to the end of the last note
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Rendered audio as a 16-bit stereo WAV file
+    pub fn to_wav(&self) -> Vec<u8> {
+        let data_len = self.frames.len() as u32 * 4;
+        let mut bytes = Vec::with_capacity(44 + data_len as usize);
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        bytes.extend_from_slice(&2u16.to_le_bytes()); // Stereo
+        bytes.extend_from_slice(&self.sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(self.sample_rate * 4).to_le_bytes());
+        bytes.extend_from_slice(&4u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+
+        for frame in &self.frames {
+            for sample in [frame.left, frame.right] {
+                let sample = (sample.clamp(-1., 1.) * i16::MAX as f32) as i16;
+                bytes.extend_from_slice(&sample.to_le_bytes());
+            }
+        }
+        bytes
+    }
+}
+
+/// Mixes each note in at its time, pitched with the high quality resampler
+impl NoteSink for OfflineMixer {
+    fn trigger_note(&mut self, note: &NoteEvent) {
+        let sound_id = audio::sound_id(note.instrument, self.first_custom);
+        let Some((frames, tone)) = self.sounds.get(&sound_id) else {
+            log::error!("Sound ID {} not found", sound_id);
+            return;
+        };
+
+        let cents = AudioEngine::note_cents(note.key, note.pitch, *tone);
+        let frames = if cents == 0 {
+            frames.clone()
+        } else {
+            self.pitch_cache.get_or_render(sound_id, cents, frames)
+        };
+
+        // Same loudness curve as the realtime engine, which sets the volume in decibels
+        let gain = (note.velocity as f32 * self.volume / 100.).sqrt();
+        let pan = note.stereo_position();
+        let (left_gain, right_gain) = (gain * (1. - pan).min(1.), gain * (1. + pan).min(1.));
+
+        let start = (note.time.max(0.) * self.sample_rate as f64).round() as usize;
+        if self.frames.len() < start + frames.len() {
+            self.frames.resize(start + frames.len(), Frame::ZERO);
+        }
+        for (mixed, frame) in self.frames[start..].iter_mut().zip(frames.iter()) {
+            mixed.left += frame.left * left_gain;
+            mixed.right += frame.right * right_gain;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(time: f64, panning: u8) -> NoteEvent {
+        NoteEvent {
+            tick: 0,
+            time,
+            instrument: 0,
+            key: 45,
+            velocity: 100,
+            panning,
+            pitch: 0,
+        }
+    }
+
+    #[test]
+    fn test_notes_are_mixed_at_their_time() {
+        let mut mixer = OfflineMixer::new(DEFAULT_SAMPLE_RATE).unwrap();
+        mixer.trigger_note(&note(0.5, 100));
+
+        let start = DEFAULT_SAMPLE_RATE as usize / 2;
+        let frames = mixer.frames();
+        assert!(frames.len() > start);
+        assert!(frames[..start].iter().all(|frame| *frame == Frame::ZERO));
+        assert!(frames[start..].iter().any(|frame| frame.left != 0.));
+
+        let wav = mixer.to_wav();
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(wav.len(), 44 + frames.len() * 4);
+    }
+
+    #[test]
+    fn test_hard_left_note_is_silent_on_the_right() {
+        let mut mixer = OfflineMixer::new(DEFAULT_SAMPLE_RATE).unwrap();
+        mixer.trigger_note(&note(0., 0));
+
+        assert!(mixer.frames().iter().all(|frame| frame.right == 0.));
+        assert!(mixer.frames().iter().any(|frame| frame.left != 0.));
+    }
+
+    #[test]
+    fn test_hard_right_note_is_silent_on_the_left() {
+        let mut mixer = OfflineMixer::new(DEFAULT_SAMPLE_RATE).unwrap();
+        mixer.trigger_note(&note(0., 200));
+
+        assert!(mixer.frames().iter().all(|frame| frame.left == 0.));
+        assert!(mixer.frames().iter().any(|frame| frame.right != 0.));
+    }
+}
@@ -0,0 +1,90 @@
+/// NBT tag, written big-endian as Minecraft expects
+pub enum Tag {
+    Short(i16),
+    Int(i32),
+    ByteArray(Vec<u8>),
+    /// Empty lists are written with the end tag as element type
+    List(Vec<Tag>),
+    /// Entries in insertion order
+    Compound(Vec<(String, Tag)>),
+    IntArray(Vec<i32>),
+}
+
+impl Tag {
+    fn id(&self) -> u8 {
+        match self {
+            Tag::Short(_) => 2,
+            Tag::Int(_) => 3,
+            Tag::ByteArray(_) => 7,
+            Tag::List(_) => 9,
+            Tag::Compound(_) => 10,
+            Tag::IntArray(_) => 11,
+        }
+    }
+
+    fn write_payload(&self, out: &mut Vec<u8>) {
+        match self {
+            Tag::Short(value) => out.extend_from_slice(&value.to_be_bytes()),
+            Tag::Int(value) => out.extend_from_slice(&value.to_be_bytes()),
+            Tag::ByteArray(bytes) => {
+                out.extend_from_slice(&(bytes.len() as i32).to_be_bytes());
+                out.extend_from_slice(bytes);
+            }
+            Tag::List(items) => {
+                out.push(items.first().map_or(0, Tag::id));
+                out.extend_from_slice(&(items.len() as i32).to_be_bytes());
+                for item in items {
+                    item.write_payload(out);
+                }
+            }
+            Tag::Compound(entries) => {
+                for (name, tag) in entries {
+                    out.push(tag.id());
+                    write_string(out, name);
+                    tag.write_payload(out);
+                }
+                out.push(0); // end tag
+            }
+            Tag::IntArray(values) => {
+                out.extend_from_slice(&(values.len() as i32).to_be_bytes());
+                for value in values {
+                    out.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+        }
+    }
+}
+
+/// Strings are a `u16` byte length followed by the bytes. Minecraft uses modified UTF-8, which
+/// only differs from UTF-8 for null and supplementary characters.
+fn write_string(out: &mut Vec<u8>, value: &str) {
+    out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    out.extend_from_slice(value.as_bytes());
+}
+
+/// Serializes a root tag with its name, uncompressed
+pub fn write_root(name: &str, tag: &Tag) -> Vec<u8> {
+    let mut out = vec![tag.id()];
+    write_string(&mut out, name);
+    tag.write_payload(&mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_root() {
+        let tag = Tag::Compound(vec![
+            ("a".to_string(), Tag::Short(1)),
+            ("l".to_string(), Tag::List(vec![])),
+        ]);
+        assert_eq!(
+            write_root("r", &tag),
+            [
+                10, 0, 1, b'r', 2, 0, 1, b'a', 0, 1, 9, 0, 1, b'l', 0, 0, 0, 0, 0, 0
+            ]
+        );
+    }
+}
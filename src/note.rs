@@ -1,20 +1,18 @@
+#[cfg(feature = "gui")]
 use std::collections::HashMap;
 
+#[cfg(feature = "gui")]
 use macroquad::{
     color::{self, Color},
-    math::{Rect, Vec2},
-    text::{TextParams, draw_text_ex, measure_text},
-    texture::{DrawTextureParams, Texture2D, draw_texture_ex},
+    math::Rect,
 };
 use nbs_rs;
 
-use crate::piano;
-
-pub fn load_note_texture() -> Texture2D {
-    let note_image_bytes = include_bytes!("../assets/textures/note_block.png");
-    let note_texture = Texture2D::from_file_with_format(note_image_bytes, None);
-    note_texture
-}
+#[cfg(feature = "gui")]
+use crate::{
+    canvas::{Canvas, Sprite},
+    piano,
+};
 
 #[derive(Clone, Debug)]
 pub struct NoteBlock {
@@ -22,13 +20,20 @@ pub struct NoteBlock {
     pub instrument: u8,
     pub key: u8,
     pub velocity: u8,
-    pub panning: i8,
+    pub panning: u8,
     pub pitch: i16,
 }
 
+/// Number of ticks to play. `song_length` is the index of the last tick, and notes may be
+/// saved past it.
+pub fn song_ticks(song: &nbs_rs::NbsFile) -> usize {
+    let last_note = song.notes.iter().map(|note| note.tick as usize).max();
+    last_note.unwrap_or(0).max(song.header.song_length as usize) + 1
+}
+
 pub fn get_note_blocks(song: &nbs_rs::NbsFile) -> Vec<Vec<NoteBlock>> {
     // Pre allocate the ticks so it doesn't have to resize the on each iteration
-    let mut note_blocks: Vec<Vec<NoteBlock>> = vec![Vec::new(); song.header.song_length as usize];
+    let mut note_blocks: Vec<Vec<NoteBlock>> = vec![Vec::new(); song_ticks(song)];
 
     for note in &song.notes {
         let tick = note.tick as usize;
@@ -40,7 +45,7 @@ pub fn get_note_blocks(song: &nbs_rs::NbsFile) -> Vec<Vec<NoteBlock>> {
                     instrument: note.instrument,
                     key: note.key,
                     velocity: note.velocity,
-                    panning: note.panning,
+                    panning: note.panning as u8,
                     pitch: note.pitch,
                 });
             } else {
@@ -49,8 +54,8 @@ pub fn get_note_blocks(song: &nbs_rs::NbsFile) -> Vec<Vec<NoteBlock>> {
                     was_played: false,
                     instrument: note.instrument,
                     key: note.key,
-                    velocity: note.velocity * layer.volume,
-                    panning: note.panning * layer.panning,
+                    velocity: (note.velocity as u32 * layer.volume as u32 / 100) as u8,
+                    panning: combine_panning(note.panning as u8, layer.panning as u8),
                     pitch: note.pitch,
                 });
             }
@@ -66,6 +71,13 @@ pub fn get_note_blocks(song: &nbs_rs::NbsFile) -> Vec<Vec<NoteBlock>> {
     note_blocks
 }
 
+/// Adds the offsets from center of two NBS panning bytes (0-200, 100 is center)
+fn combine_panning(note_panning: u8, layer_panning: u8) -> u8 {
+    let pan = note_panning as i32 + layer_panning as i32 - 100;
+    pan.clamp(0, 200) as u8
+}
+
+#[cfg(feature = "gui")]
 fn hsl_to_rgb(h: f64, s: f64, l: f64) -> (u8, u8, u8) {
     let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
     let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
@@ -89,6 +101,7 @@ fn hsl_to_rgb(h: f64, s: f64, l: f64) -> (u8, u8, u8) {
     (r, g, b)
 }
 
+#[cfg(feature = "gui")]
 pub fn generate_instrument_palette() -> HashMap<u8, Color> {
     let mut instrument_colors = HashMap::new();
 
@@ -138,14 +151,15 @@ pub fn generate_instrument_palette() -> HashMap<u8, Color> {
     instrument_colors
 }
 
+#[cfg(feature = "gui")]
 pub fn draw_notes(
+    canvas: &mut dyn Canvas,
     window_width: f32,
     window_height: f32,
     all_keys: &Vec<piano::PianoKey>,
     key_map: &HashMap<u8, usize>,
     note_blocks: &Vec<Vec<NoteBlock>>,
     piano_props: &piano::PianoProps,
-    note_texture: &Texture2D,
     current_tick: f32,
     note_dim: f32,
     key_spacing: f32,
@@ -162,8 +176,6 @@ pub fn draw_notes(
     // Count notes being rendered
     let mut notes_rendered = 0;
 
-    let font = crate::font::FONT.get().unwrap();
-
     for tick in window_start_tick as usize..window_end_tick as usize {
         let tick_f32 = tick as f32;
         if let Some(notes) = note_blocks.get(tick as usize) {
@@ -201,15 +213,13 @@ pub fn draw_notes(
                         color.a = 0.90;
 
                         // Draw the note texture
-                        draw_texture_ex(
-                            note_texture,
+                        canvas.draw_sprite(
+                            Sprite::NoteBlock,
                             note_rect.x,
                             note_rect.y,
+                            note_rect.w,
+                            note_rect.h,
                             color,
-                            DrawTextureParams {
-                                dest_size: Some(Vec2::new(note_rect.w as f32, note_rect.h as f32)),
-                                ..Default::default()
-                            },
                         );
 
                         // Draw the tone (note name) on the note
@@ -221,17 +231,16 @@ pub fn draw_notes(
                         let mut font_size = max_font_size;
 
                         // Measure text width and height
-                        let mut text_width = measure_text(text, Some(&font), font_size, 1.0).width;
-                        let mut text_height =
-                            measure_text(text, Some(&font), font_size, 1.0).height;
+                        let mut text_width = canvas.measure_text(text, font_size).width;
+                        let mut text_height = canvas.measure_text(text, font_size).height;
 
                         // Adjust font size if the text is too large
                         while (text_width > note_rect.w - 5. || text_height > note_rect.h - 5.)
                             && font_size > min_font_size
                         {
                             font_size -= 1;
-                            text_width = measure_text(text, Some(&font), font_size, 1.0).width;
-                            text_height = measure_text(text, Some(&font), font_size, 1.0).height;
+                            text_width = canvas.measure_text(text, font_size).width;
+                            text_height = canvas.measure_text(text, font_size).height;
                         }
 
                         // Center text horizontally and vertically within the note block
@@ -239,17 +248,7 @@ pub fn draw_notes(
                         let text_y = note_rect.y + (note_rect.h) / 2.0;
 
                         // Draw the text
-                        draw_text_ex(
-                            text,
-                            text_x,
-                            text_y,
-                            TextParams {
-                                font: Some(&font),
-                                font_size,
-                                color: color::WHITE,
-                                ..Default::default()
-                            },
-                        );
+                        canvas.draw_text(text, text_x, text_y, font_size, color::WHITE);
 
                         // Increment notes rendered count
                         notes_rendered += 1;
@@ -260,3 +259,53 @@ pub fn draw_notes(
     }
     notes_rendered
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combine_panning() {
+        assert_eq!(combine_panning(100, 100), 100);
+        assert_eq!(combine_panning(150, 100), 150);
+        assert_eq!(combine_panning(150, 80), 130);
+        assert_eq!(combine_panning(180, 180), 200);
+        assert_eq!(combine_panning(20, 30), 0);
+    }
+
+    #[test]
+    fn test_notes_on_the_last_tick_are_kept() {
+        let bytes = include_bytes!("../test-assets/nyan_cat.nbs");
+        let song = nbs_rs::NbsParser::new(bytes).parse().unwrap();
+        let last_tick = song
+            .notes
+            .iter()
+            .map(|note| note.tick as usize)
+            .max()
+            .unwrap();
+        assert_eq!(last_tick, song.header.song_length as usize);
+
+        let note_blocks = get_note_blocks(&song);
+        assert_eq!(note_blocks.len(), last_tick + 1);
+        assert_eq!(
+            note_blocks.iter().map(Vec::len).sum::<usize>(),
+            song.notes.len()
+        );
+    }
+
+    #[test]
+    fn test_layer_volume_scales_velocity() {
+        let bytes = include_bytes!("../test-assets/nyan_cat.nbs");
+        let mut song = nbs_rs::NbsParser::new(bytes).parse().unwrap();
+        let first = &song.notes[0];
+        let (tick, layer, velocity) = (first.tick as usize, first.layer as usize, first.velocity);
+        assert!(layer < song.layers.len());
+        song.layers[layer].volume = 50;
+        song.layers[layer].panning = 100;
+
+        let note_blocks = get_note_blocks(&song);
+        let note = &note_blocks[tick][0];
+        assert_eq!(note.velocity as u32, velocity as u32 * 50 / 100);
+        assert_eq!(note.panning, song.notes[0].panning as u8);
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{
+    song::SongData,
+    utils::{decode_text, wrap_text},
+};
+use macroquad::{
+    color::{self, Color},
+    shapes::draw_rectangle,
+    text::{Font, TextParams, draw_text_ex, measure_text},
+};
+
+/// NBS ticks per beat, as used by Note Block Studio for its BPM display
+const TICKS_PER_BEAT: f32 = 4.0;
+const FONT_SIZE: u16 = 20;
+const LINE_HEIGHT: f32 = 24.0;
+const PADDING: f32 = 20.0;
+const LABEL_WIDTH: f32 = 200.0;
+
+/// Song details shown over the player with the I key
+pub struct SongInfoOverlay {
+    rows: Vec<(&'static str, String)>,
+    description: String,
+}
+
+fn text_or_dash(bytes: &[u8]) -> String {
+    let text = decode_text(bytes).trim().to_string();
+    if text.is_empty() {
+        "-".to_string()
+    } else {
+        text
+    }
+}
+
+impl SongInfoOverlay {
+    pub fn new(song_data: &SongData) -> Self {
+        let song = &song_data.song;
+        let header = &song.header;
+        let tempo = header.tempo as f32 / 100.0;
+        let custom_instruments = if song.instruments.is_empty() {
+            "none".to_string()
+        } else {
+            song.instruments
+                .iter()
+                .map(|instrument| decode_text(&instrument.name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let mut rows = vec![("Title", song_data.title())];
+        for (label, text) in [
+            ("Author", &header.song_author),
+            ("Original author", &header.original_author),
+        ] {
+            let text = decode_text(text).trim().to_string();
+            if !text.is_empty() {
+                rows.push((label, text));
+            }
+        }
+        rows.extend([
+            (
+                "Tempo",
+                format!(
+                    "{:.2} TPS ({:.0} BPM)",
+                    tempo,
+                    tempo * 60.0 / TICKS_PER_BEAT
+                ),
+            ),
+            ("Time signature", format!("{}/4", header.time_signature)),
+            ("Layers", song.layers.len().to_string()),
+            ("Custom instruments", custom_instruments),
+            ("Imported from", text_or_dash(&header.song_origin)),
+            ("Format", song_data.format.describe()),
+        ]);
+
+        Self {
+            rows,
+            description: text_or_dash(&header.description),
+        }
+    }
+
+    pub fn draw(&self, window_width: f32, window_height: f32, font: &Font) {
+        let text_parameters = TextParams {
+            font_size: FONT_SIZE,
+            font: Some(font),
+            color: color::BLACK,
+            ..Default::default()
+        };
+        let measure = |text: &str| measure_text(text, Some(font), FONT_SIZE, 1.0).width;
+
+        let panel_width = (window_width - 2. * PADDING).min(900.);
+        let value_width = panel_width - LABEL_WIDTH - 2. * PADDING;
+
+        // Wrap every value to the panel before sizing it
+        let mut lines: Vec<(&str, String)> = Vec::new();
+        for (label, value) in &self.rows {
+            for (index, line) in wrap_text(value, value_width, measure)
+                .into_iter()
+                .enumerate()
+            {
+                lines.push((if index == 0 { label } else { "" }, line));
+            }
+        }
+        let description = wrap_text(&self.description, panel_width - 2. * PADDING, measure);
+
+        let panel_height =
+            (lines.len() + description.len() + 4) as f32 * LINE_HEIGHT + 2. * PADDING;
+        let panel_x = (window_width - panel_width) / 2.;
+        let panel_y = ((window_height - panel_height) / 2.).max(0.);
+
+        draw_rectangle(
+            panel_x,
+            panel_y,
+            panel_width,
+            panel_height,
+            Color::new(1., 1., 1., 0.9),
+        );
+
+        let x = panel_x + PADDING;
+        let mut y = panel_y + PADDING + LINE_HEIGHT;
+        draw_text_ex(
+            "Song Info",
+            x,
+            y,
+            TextParams {
+                color: color::RED,
+                ..text_parameters.clone()
+            },
+        );
+
+        for (label, value) in &lines {
+            y += LINE_HEIGHT;
+            draw_text_ex(label, x, y, text_parameters.clone());
+            draw_text_ex(value, x + LABEL_WIDTH, y, text_parameters.clone());
+        }
+
+        y += LINE_HEIGHT;
+        draw_text_ex("Description", x, y, text_parameters.clone());
+        for line in &description {
+            y += LINE_HEIGHT;
+            draw_text_ex(line, x, y, text_parameters.clone());
+        }
+
+        y += LINE_HEIGHT * 1.5;
+        draw_text_ex(
+            "Press I to close",
+            x,
+            y,
+            TextParams {
+                color: color::DARKGRAY,
+                ..text_parameters.clone()
+            },
+        );
+    }
+}
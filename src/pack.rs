@@ -0,0 +1,230 @@
+#[cfg(feature = "zip")]
+use std::io::{Cursor, Write};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use nbs_rs::NbsFile;
+#[cfg(feature = "zip")]
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+use crate::utils::decode_text;
+#[cfg(feature = "zip")]
+use crate::writer;
+
+/// Name of the song inside a song pack
+pub const PACK_SONG_NAME: &str = "song.nbs";
+/// Folder holding the custom sounds inside a song pack
+pub const PACK_SOUNDS_DIR: &str = "sounds";
+
+/// Sample of a custom instrument, stored in the pack as `sounds/<name>`, see `pack_sound_name`
+pub struct PackSound {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Path of a custom instrument's sample inside a song pack
+pub fn pack_sound_path(name: &str) -> String {
+    format!("{}/{}", PACK_SOUNDS_DIR, name)
+}
+
+/// Name of a custom instrument's sample inside a song pack: the name of its sound file, like
+/// Note Block World packs, or the instrument name when it has no file
+pub fn pack_sound_name(file: &str, name: &str) -> String {
+    let file = file.replace('\\', "/");
+    match file.rsplit('/').next().unwrap_or("") {
+        "" => name.to_string(),
+        file_name => file_name.to_string(),
+    }
+}
+
+/// Names of the files inside a ZIP archive, empty if it can't be read
+#[cfg(feature = "zip")]
+pub fn zip_file_names(bytes: &[u8]) -> Vec<String> {
+    zip::ZipArchive::new(Cursor::new(bytes))
+        .map(|zip| zip.file_names().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(not(feature = "zip"))]
+pub fn zip_file_names(_bytes: &[u8]) -> Vec<String> {
+    Vec::new()
+}
+
+/// Folders searched for custom sounds: the given ones, then the song's folder and its `sounds`
+/// subfolder
+pub fn sound_search_dirs(song_path: &Path, extra_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut dirs = extra_dirs.to_vec();
+    let song_dir = song_path
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    dirs.push(song_dir.join("sounds"));
+    dirs.push(song_dir);
+    dirs
+}
+
+/// Looks for the sample of a custom instrument by its sound file path, then by file and
+/// instrument name
+pub fn find_sound(file: &str, name: &str, search_dirs: &[PathBuf]) -> Option<PathBuf> {
+    // Note Block Studio stores Windows paths relative to its Sounds folder
+    let file = file.replace('\\', "/");
+    let file_name = file.rsplit('/').next().unwrap_or("");
+
+    let candidates = [file.as_str(), file_name, name];
+    search_dirs.iter().find_map(|dir| {
+        candidates
+            .iter()
+            .filter(|candidate| !candidate.is_empty())
+            .map(|candidate| dir.join(candidate))
+            .find(|path| path.is_file())
+    })
+}
+
+/// Reads the sample of every custom instrument from disk, failing with the list of
+/// instruments that couldn't be resolved
+pub fn resolve_sounds(song: &NbsFile, search_dirs: &[PathBuf]) -> Result<Vec<PackSound>, String> {
+    let mut sounds = Vec::new();
+    let mut missing = Vec::new();
+
+    for instrument in &song.instruments {
+        let name = decode_text(&instrument.name);
+        let file = decode_text(&instrument.file);
+
+        match find_sound(&file, &name, search_dirs) {
+            Some(path) => {
+                let data = fs::read(&path)
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                sounds.push(PackSound {
+                    name: pack_sound_name(&file, &name),
+                    data,
+                });
+            }
+            None => missing.push(format!("{} ({})", name, file)),
+        }
+    }
+
+    if missing.is_empty() {
+        Ok(sounds)
+    } else {
+        Err(format!(
+            "Custom instruments without a sound file: {}",
+            missing.join(", ")
+        ))
+    }
+}
+
+/// Writes a deflated ZIP archive holding the given `(path, data)` files
+#[cfg(feature = "zip")]
+pub fn write_zip(files: &[(String, Vec<u8>)]) -> Result<Vec<u8>, String> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    for (name, data) in files {
+        zip.start_file(name.as_str(), options)
+            .and_then(|_| zip.write_all(data).map_err(Into::into))
+            .map_err(|e| format!("Failed to add {} to the archive: {}", name, e))?;
+    }
+
+    let cursor = zip
+        .finish()
+        .map_err(|e| format!("Failed to write the archive: {}", e))?;
+    Ok(cursor.into_inner())
+}
+
+/// Bundles a song and its custom sounds in the layout read by `song::load_nbs_file`
+#[cfg(feature = "zip")]
+pub fn write_pack(song: &NbsFile, sounds: &[PackSound]) -> Result<Vec<u8>, String> {
+    let mut files = vec![(PACK_SONG_NAME.to_string(), writer::write_nbs(song))];
+    for sound in sounds {
+        // Instruments can share a sound file
+        let path = pack_sound_path(&sound.name);
+        if !files.iter().any(|(name, _)| *name == path) {
+            files.push((path, sound.data.clone()));
+        }
+    }
+    write_zip(&files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "zip")]
+    fn test_write_pack_loads_back() {
+        use std::io::Read;
+
+        let original = include_bytes!("../test-assets/Mesmerizer.zip") as &[u8];
+        let song = crate::song::load_nbs_file(Some(original)).song;
+        let mut zip = zip::ZipArchive::new(Cursor::new(original)).unwrap();
+        let sounds: Vec<PackSound> = song
+            .instruments
+            .iter()
+            .map(|instrument| {
+                let name = pack_sound_name(
+                    &decode_text(&instrument.file),
+                    &decode_text(&instrument.name),
+                );
+                let mut data = Vec::new();
+                zip.by_name(&pack_sound_path(&name))
+                    .unwrap()
+                    .read_to_end(&mut data)
+                    .unwrap();
+                PackSound { name, data }
+            })
+            .collect();
+        assert!(!sounds.is_empty());
+
+        let pack = write_pack(&song, &sounds).unwrap();
+        let loaded = crate::song::load_nbs_file(Some(&pack));
+
+        assert_eq!(loaded.song.notes.len(), song.notes.len());
+        let loaded_sounds: Vec<&Vec<u8>> = loaded
+            .extra_sounds
+            .iter()
+            .map(|sound| &sound.as_ref().unwrap().0)
+            .collect();
+        let written_sounds: Vec<&Vec<u8>> = sounds.iter().map(|sound| &sound.data).collect();
+        assert_eq!(loaded_sounds, written_sounds);
+
+        // A missing sound leaves a gap instead of moving the next sounds onto other instruments
+        let pack = write_pack(&song, &sounds[1..]).unwrap();
+        let loaded = crate::song::load_nbs_file(Some(&pack));
+        assert!(loaded.extra_sounds[0].is_none());
+        assert_eq!(
+            loaded.extra_sounds[1].as_ref().map(|(data, _)| data),
+            Some(&sounds[1].data)
+        );
+    }
+
+    #[test]
+    fn test_pack_sound_name() {
+        assert_eq!(
+            pack_sound_name("fbb8115856475678b59b9c923a17bb3c1c685629", ""),
+            "fbb8115856475678b59b9c923a17bb3c1c685629"
+        );
+        assert_eq!(
+            pack_sound_name("custom\\drums\\kick.ogg", "Kick"),
+            "kick.ogg"
+        );
+        assert_eq!(pack_sound_name("", "Kick"), "Kick");
+    }
+
+    #[test]
+    fn test_find_sound_windows_path() {
+        let dir = std::env::temp_dir().join("nbs-player-rs-pack-test");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("kick.ogg"), b"ogg").unwrap();
+
+        let search_dirs = vec![dir.clone()];
+        assert_eq!(
+            find_sound("drums\\kick.ogg", "Kick", &search_dirs),
+            Some(dir.join("kick.ogg"))
+        );
+        assert_eq!(find_sound("snare.ogg", "Snare", &search_dirs), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
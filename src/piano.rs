@@ -1,12 +1,8 @@
 use std::collections::HashMap;
 
-use macroquad::{
-    color,
-    math::Vec2,
-    shapes::draw_rectangle,
-    text::{TextParams, draw_text_ex, measure_text},
-    texture::{DrawTextureParams, Texture2D, draw_texture_ex},
-};
+use macroquad::color;
+
+use crate::canvas::{Canvas, Sprite};
 
 #[derive(Debug)]
 pub struct PianoProps {
@@ -15,8 +11,6 @@ pub struct PianoProps {
     pub white_key_height: f32,
     pub black_key_width: f32,
     pub black_key_height: f32,
-    pub white_key_texture: Texture2D,
-    pub black_key_texture: Texture2D,
 }
 
 #[derive(Clone, Debug)]
@@ -216,6 +210,7 @@ pub fn update_key_animation(keys: &mut [PianoKey], delta_time: f32) {
 }
 
 pub fn draw_piano_keys(
+    canvas: &mut dyn Canvas,
     window_width: f32,
     window_height: f32,
     all_keys: &Vec<PianoKey>,
@@ -226,8 +221,6 @@ pub fn draw_piano_keys(
     let white_key_height = piano_props.white_key_height;
     let black_key_width = piano_props.black_key_width;
     let black_key_height = piano_props.black_key_height;
-    let white_key_texture = &piano_props.white_key_texture;
-    let black_key_texture = &piano_props.black_key_texture;
 
     let total_white_keys = all_keys.iter().filter(|k| k.is_white).count() as f32;
     let total_width = total_white_keys * (white_key_width + key_spacing) - key_spacing;
@@ -235,12 +228,11 @@ pub fn draw_piano_keys(
     let piano_x = (window_width - total_width) / 2.0;
     let piano_y = window_height - white_key_height;
 
-    let font = crate::font::FONT.get().unwrap();
     let max_font_size = 18;
     let min_font_size = 8;
 
     // draw a background for the piano
-    draw_rectangle(
+    canvas.draw_rectangle(
         (window_width - total_width) / 2.0,
         window_height - white_key_height,
         total_width,
@@ -249,7 +241,7 @@ pub fn draw_piano_keys(
     );
 
     for (i, key) in all_keys.iter().enumerate() {
-        let (x_pos, y_pos, width, height, texture, text_color) =
+        let (x_pos, y_pos, width, height, sprite, text_color) =
             match (key.is_white, key.white_key_index) {
                 (true, _) => {
                     let x = piano_x + (i as f32 * (white_key_width + key_spacing));
@@ -259,7 +251,7 @@ pub fn draw_piano_keys(
                         y,
                         white_key_width,
                         white_key_height,
-                        white_key_texture,
+                        Sprite::WhiteKey,
                         color::BLACK,
                     )
                 }
@@ -271,7 +263,7 @@ pub fn draw_piano_keys(
                         y,
                         black_key_width,
                         black_key_height,
-                        black_key_texture,
+                        Sprite::BlackKey,
                         color::WHITE,
                     )
                 }
@@ -279,46 +271,31 @@ pub fn draw_piano_keys(
             };
 
         // Draw key with texture
-        draw_texture_ex(
-            texture,
-            x_pos,
-            y_pos,
-            color::WHITE,
-            DrawTextureParams {
-                dest_size: Some(Vec2::new(width, height)),
-                ..Default::default()
-            },
-        );
+        canvas.draw_sprite(sprite, x_pos, y_pos, width, height, color::WHITE);
 
         // Calculate font size to fit within the key
         let mut font_size = max_font_size;
-        let mut text_width = measure_text(&key.label, Some(font), font_size, 1.0).width;
+        let mut text_width = canvas.measure_text(&key.label, font_size).width;
         while text_width > width - 5.0 && font_size > min_font_size {
             font_size -= 1;
-            text_width = measure_text(&key.label, Some(font), font_size, 1.0).width;
+            text_width = canvas.measure_text(&key.label, font_size).width;
         }
 
         // Center text horizontally within the key
         let text_x = x_pos + (width - text_width) / 2.0;
 
         // Draw label
-        draw_text_ex(
+        canvas.draw_text(
             &key.label,
             text_x,
             y_pos + height - height * 0.25,
-            TextParams {
-                font: Some(font),
-                font_size,
-                color: text_color,
-                ..Default::default()
-            },
+            font_size,
+            text_color,
         );
     }
 }
 
 pub fn initialize_piano_dimensions(window_width: f32, all_keys: &Vec<PianoKey>) -> PianoProps {
-    let (white_key_texture, black_key_texture) = load_piano_key_textures();
-
     let num_white_keys = all_keys.iter().filter(|k| k.is_white).count() as f32;
 
     let black_key_width_ratio = 0.8;
@@ -337,23 +314,5 @@ pub fn initialize_piano_dimensions(window_width: f32, all_keys: &Vec<PianoKey>)
         white_key_height,
         black_key_width,
         black_key_height,
-        white_key_texture,
-        black_key_texture,
     }
 }
-
-pub fn load_piano_key_textures() -> (Texture2D, Texture2D) {
-    let (key_black_bytes, key_white_bytes) = (
-        include_bytes!("../assets/textures/key_black.png"),
-        include_bytes!("../assets/textures/key_white.png"),
-    );
-    let (key_black_image, key_white_image) = (
-        Texture2D::from_file_with_format(key_black_bytes, None),
-        Texture2D::from_file_with_format(key_white_bytes, None),
-    );
-
-    key_black_image.set_filter(macroquad::texture::FilterMode::Nearest);
-    key_white_image.set_filter(macroquad::texture::FilterMode::Nearest);
-
-    (key_white_image, key_black_image)
-}
@@ -0,0 +1,402 @@
+use std::ops::Range;
+
+use crate::{
+    note::NoteBlock,
+    scheduler::Scheduler,
+    sink::NoteSink,
+    transport::{Transport, TransportCommand},
+};
+
+/// Position of the song being played and the notes left to trigger, moved by transport
+/// commands and frame times
+#[derive(Debug)]
+pub struct Playback {
+    pub transport: Transport,
+    scheduler: Scheduler,
+    notes_per_second: f32,
+    /// Duration in seconds
+    duration: f32,
+}
+
+impl Playback {
+    /// Playback of a song of `song_length` ticks, paused at its start
+    pub fn new(song_length: usize, notes_per_second: f32) -> Self {
+        let mut playback = Self {
+            transport: Transport::default(),
+            scheduler: Scheduler::new(0),
+            notes_per_second,
+            duration: 0.,
+        };
+        playback.load(song_length, notes_per_second);
+        playback
+    }
+
+    /// Switches to the start of another song, keeping the pause state and speed
+    pub fn load(&mut self, song_length: usize, notes_per_second: f32) {
+        self.scheduler = Scheduler::new(song_length);
+        self.notes_per_second = notes_per_second;
+        self.duration = song_length as f32 / notes_per_second;
+        self.transport.elapsed_time = 0.;
+    }
+
+    pub fn notes_per_second(&self) -> f32 {
+        self.notes_per_second
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    pub fn current_tick(&self) -> f32 {
+        self.transport.elapsed_time * self.notes_per_second
+    }
+
+    pub fn is_end(&self) -> bool {
+        self.transport.is_end(self.duration)
+    }
+
+    /// Rewinds to the start, cutting the notes still ringing
+    pub fn restart(&mut self, sink: &mut dyn NoteSink) {
+        sink.stop_all();
+        self.scheduler.restart();
+        self.transport.elapsed_time = 0.;
+    }
+
+    /// Applies a transport command, returning whether the position jumped. The volume and
+    /// playlist commands are left to the caller.
+    pub fn apply(&mut self, command: TransportCommand, sink: &mut dyn NoteSink) -> bool {
+        match command {
+            TransportCommand::Play => {
+                let restarted = self.is_end();
+                if restarted {
+                    self.restart(sink);
+                }
+                self.transport.is_paused = false;
+                restarted
+            }
+            TransportCommand::Pause => {
+                self.transport.is_paused = true;
+                false
+            }
+            // Restarts at the end of the song, which leaves it paused at its start
+            TransportCommand::TogglePause => {
+                let restarted = self.is_end();
+                if restarted {
+                    self.restart(sink);
+                    self.transport.is_paused = false;
+                }
+                self.transport.is_paused = !self.transport.is_paused;
+                restarted
+            }
+            TransportCommand::Seek(_) | TransportCommand::SeekBy(_) => {
+                let Some(target) = self.transport.seek_target(command, self.duration) else {
+                    return false;
+                };
+                sink.stop_all();
+                self.scheduler
+                    .seek((target * self.notes_per_second) as usize);
+                self.transport.elapsed_time = target;
+                true
+            }
+            TransportCommand::SetSpeed(speed) => {
+                self.transport.set_speed(speed);
+                false
+            }
+            TransportCommand::SetVolume(_)
+            | TransportCommand::Next
+            | TransportCommand::Previous => false,
+        }
+    }
+
+    /// Moves the song forward by a frame and triggers the notes it reached, returning their
+    /// ticks
+    pub fn advance(
+        &mut self,
+        delta_time: f32,
+        note_blocks: &[Vec<NoteBlock>],
+        sink: &mut dyn NoteSink,
+    ) -> Range<usize> {
+        self.transport.advance(delta_time, self.duration);
+        if self.transport.is_paused {
+            return 0..0;
+        }
+        self.scheduler.trigger_due(
+            self.current_tick(),
+            self.notes_per_second,
+            note_blocks,
+            sink,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{note, sink::RecordingSink};
+
+    /// Frame times of the harness, irregular but the same on every run
+    struct FrameClock {
+        state: u64,
+        frame: usize,
+    }
+
+    impl FrameClock {
+        fn new() -> Self {
+            Self {
+                state: 0x9e3779b97f4a7c15,
+                frame: 0,
+            }
+        }
+
+        /// 1 to 40 ms, with a 300 ms hitch every 97 frames
+        fn next_delta(&mut self) -> f32 {
+            // xorshift64
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 7;
+            self.state ^= self.state << 17;
+            self.frame += 1;
+
+            if self.frame % 97 == 0 {
+                0.3
+            } else {
+                0.001 + (self.state % 39_000) as f32 / 1_000_000.
+            }
+        }
+    }
+
+    /// Plays a song on a recording sink with a simulated frame clock
+    struct Harness {
+        playback: Playback,
+        note_blocks: Vec<Vec<NoteBlock>>,
+        /// Notes expected on each tick
+        notes_per_tick: Vec<usize>,
+        sink: RecordingSink,
+        clock: FrameClock,
+    }
+
+    impl Harness {
+        fn new(note_blocks: Vec<Vec<NoteBlock>>, notes_per_second: f32) -> Self {
+            Self {
+                playback: Playback::new(note_blocks.len(), notes_per_second),
+                notes_per_tick: note_blocks.iter().map(Vec::len).collect(),
+                note_blocks,
+                sink: RecordingSink::default(),
+                clock: FrameClock::new(),
+            }
+        }
+
+        fn load(bytes: &[u8]) -> Self {
+            let song = crate::song::load_nbs_file(Some(bytes)).song;
+            let mut harness = Self::new(
+                note::get_note_blocks(&song),
+                song.header.tempo as f32 / 100.,
+            );
+            // Counted from the song rather than the note blocks, so notes left out of the
+            // blocks show up as not played
+            harness.notes_per_tick = vec![0; harness.note_blocks.len()];
+            for note in &song.notes {
+                harness.notes_per_tick[note.tick as usize] += 1;
+            }
+            harness
+        }
+
+        fn command(&mut self, command: TransportCommand) -> bool {
+            self.playback.apply(command, &mut self.sink)
+        }
+
+        fn frame(&mut self) -> f32 {
+            let delta = self.clock.next_delta();
+            self.playback
+                .advance(delta, &self.note_blocks, &mut self.sink);
+            delta
+        }
+
+        /// Runs frames for `seconds` of wall-clock time
+        fn run(&mut self, seconds: f32) {
+            let mut elapsed = 0.;
+            while elapsed < seconds {
+                elapsed += self.frame();
+            }
+        }
+
+        fn run_to_end(&mut self) {
+            assert!(!self.playback.transport.is_paused);
+            while !self.playback.is_end() {
+                self.frame();
+            }
+        }
+
+        /// Ticks up to the current position
+        fn reached(&self) -> usize {
+            (self.playback.current_tick().floor() as usize + 1).min(self.note_blocks.len())
+        }
+
+        /// Tick of every note of `ticks`, in the order they should be triggered
+        fn expected(&self, ticks: Range<usize>) -> Vec<usize> {
+            ticks
+                .flat_map(|tick| std::iter::repeat_n(tick, self.notes_per_tick[tick]))
+                .collect()
+        }
+
+        fn assert_played(&self, segments: &[Range<usize>]) {
+            let expected: Vec<usize> = segments
+                .iter()
+                .flat_map(|ticks| self.expected(ticks.clone()))
+                .collect();
+            assert_eq!(self.sink.ticks(), expected);
+        }
+    }
+
+    const SONGS: [(&str, &[u8]); 2] = [
+        (
+            "nyan_cat.nbs",
+            include_bytes!("../test-assets/nyan_cat.nbs"),
+        ),
+        (
+            "turkish_march.nbs",
+            include_bytes!("../test-assets/turkish_march.nbs"),
+        ),
+    ];
+
+    #[test]
+    fn test_long_frames_skip_no_ticks() {
+        let note = NoteBlock {
+            was_played: false,
+            instrument: 0,
+            key: 45,
+            velocity: 100,
+            panning: 100,
+            pitch: 0,
+        };
+        let mut harness = Harness::new(vec![vec![note]; 100], 20.);
+
+        harness.command(TransportCommand::Play);
+        harness.run_to_end();
+
+        harness.assert_played(&[0..100]);
+    }
+
+    #[test]
+    fn test_every_note_plays_once_on_time() {
+        for (name, bytes) in SONGS {
+            let mut harness = Harness::load(bytes);
+            let len = harness.note_blocks.len();
+            assert!(
+                !harness.expected(0..len).is_empty(),
+                "{} has no notes",
+                name
+            );
+
+            harness.command(TransportCommand::Play);
+            harness.run_to_end();
+
+            harness.assert_played(&[0..len]);
+            let notes_per_second = harness.playback.notes_per_second() as f64;
+            for note in &harness.sink.notes {
+                assert_eq!(note.time, note.tick as f64 / notes_per_second, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pausing() {
+        let mut harness = Harness::load(SONGS[0].1);
+        let len = harness.note_blocks.len();
+
+        // Nothing plays before the song is started
+        harness.run(1.);
+        assert!(harness.sink.notes.is_empty());
+
+        harness.command(TransportCommand::Play);
+        harness.run(2.);
+        harness.command(TransportCommand::Pause);
+        let paused_at = harness.reached();
+        harness.run(5.);
+        harness.assert_played(&[0..paused_at]);
+
+        harness.command(TransportCommand::TogglePause);
+        harness.run_to_end();
+        harness.assert_played(&[0..len]);
+    }
+
+    #[test]
+    fn test_speed_changes() {
+        for (_, bytes) in SONGS {
+            let mut harness = Harness::load(bytes);
+            let len = harness.note_blocks.len();
+
+            harness.command(TransportCommand::Play);
+            harness.command(TransportCommand::SetSpeed(3.));
+            harness.run(1.);
+            harness.command(TransportCommand::SetSpeed(0.25));
+            harness.run(1.);
+            harness.command(TransportCommand::SetSpeed(1.7));
+            harness.run_to_end();
+
+            harness.assert_played(&[0..len]);
+            assert_eq!(harness.sink.stops, 0);
+        }
+    }
+
+    #[test]
+    fn test_seeking() {
+        let mut harness = Harness::load(SONGS[1].1);
+        let len = harness.note_blocks.len();
+        let duration = harness.playback.duration();
+        let notes_per_second = harness.playback.notes_per_second();
+        let seek_tick = |seconds: f32| (seconds * notes_per_second) as usize;
+
+        harness.command(TransportCommand::Play);
+        harness.run(duration / 2.);
+        let first = harness.reached();
+
+        // Back to a quarter, then forward to three quarters
+        assert!(harness.command(TransportCommand::Seek(duration / 4.)));
+        harness.run(duration / 8.);
+        let second = harness.reached();
+        let forward = harness.playback.transport.elapsed_time + duration / 4.;
+        assert!(harness.command(TransportCommand::SeekBy(duration / 4.)));
+        harness.run_to_end();
+
+        harness.assert_played(&[
+            0..first,
+            seek_tick(duration / 4.)..second,
+            seek_tick(forward)..len,
+        ]);
+        assert_eq!(harness.sink.stops, 2);
+
+        // Seeking while paused plays from the new position once resumed
+        harness.command(TransportCommand::Pause);
+        harness.command(TransportCommand::Seek(0.));
+        harness.run(1.);
+        let played = harness.sink.notes.len();
+        harness.command(TransportCommand::Play);
+        harness.run_to_end();
+        assert_eq!(harness.sink.ticks()[played..], harness.expected(0..len));
+    }
+
+    #[test]
+    fn test_looping() {
+        let mut harness = Harness::load(SONGS[0].1);
+        let len = harness.note_blocks.len();
+
+        harness.command(TransportCommand::Play);
+        harness.run_to_end();
+
+        // Play at the end starts over
+        assert!(harness.command(TransportCommand::Play));
+        harness.run_to_end();
+        harness.assert_played(&[0..len, 0..len]);
+
+        // Space at the end rewinds, waiting for another press to play
+        assert!(harness.command(TransportCommand::TogglePause));
+        assert!(harness.playback.transport.is_paused);
+        harness.run(1.);
+        harness.assert_played(&[0..len, 0..len]);
+        harness.command(TransportCommand::TogglePause);
+        harness.run_to_end();
+
+        harness.assert_played(&[0..len, 0..len, 0..len]);
+        assert_eq!(harness.sink.stops, 2);
+    }
+}
@@ -0,0 +1,312 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use quad_rand::ChooseRandom;
+
+use crate::utils::decode_text;
+
+/// Extensions of the files the player can load
+pub const SONG_EXTENSIONS: [&str; 4] = ["nbs", "zip", "mid", "midi"];
+const PLAYLIST_EXTENSIONS: [&str; 2] = ["m3u", "m3u8"];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RepeatMode {
+    #[default]
+    Off,
+    /// Starts over from the first song after the last one
+    All,
+    /// Plays the current song again when it ends
+    One,
+}
+
+impl RepeatMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "off" => Some(RepeatMode::Off),
+            "all" => Some(RepeatMode::All),
+            "one" => Some(RepeatMode::One),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RepeatMode::Off => "off",
+            RepeatMode::All => "all",
+            RepeatMode::One => "one",
+        }
+    }
+
+    fn cycle(self) -> Self {
+        match self {
+            RepeatMode::Off => RepeatMode::All,
+            RepeatMode::All => RepeatMode::One,
+            RepeatMode::One => RepeatMode::Off,
+        }
+    }
+}
+
+pub fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extensions.contains(&extension.to_lowercase().as_str()))
+}
+
+/// Files with one of the lowercase `extensions` directly inside a folder, sorted by name
+pub fn songs_in_dir(dir: &Path, extensions: &[&str]) -> Result<Vec<PathBuf>, String> {
+    let entries =
+        fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    let mut songs: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && has_extension(path, extensions))
+        .collect();
+    songs.sort();
+    Ok(songs)
+}
+
+/// Paths listed in an M3U playlist, relative paths being relative to `base_dir`
+fn parse_m3u(text: &str, base_dir: &Path) -> Vec<PathBuf> {
+    text.trim_start_matches('\u{feff}')
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| base_dir.join(line))
+        .collect()
+}
+
+/// Songs to play, in order, and the shuffle and repeat settings
+pub struct Playlist {
+    entries: Vec<PathBuf>,
+    /// Indices into `entries` in play order
+    order: Vec<usize>,
+    position: usize,
+    shuffle: bool,
+    repeat: RepeatMode,
+}
+
+impl Playlist {
+    pub fn new(entries: Vec<PathBuf>) -> Self {
+        Self {
+            order: (0..entries.len()).collect(),
+            entries,
+            position: 0,
+            shuffle: false,
+            repeat: RepeatMode::Off,
+        }
+    }
+
+    /// Builds a playlist from song files, folders of songs and M3U playlists
+    pub fn from_paths(paths: &[PathBuf]) -> Result<Self, String> {
+        let mut entries = Vec::new();
+        for path in paths {
+            if path.is_dir() {
+                let songs = songs_in_dir(path, &SONG_EXTENSIONS)?;
+                if songs.is_empty() {
+                    log::warn!("No songs found in {}", path.display());
+                }
+                entries.extend(songs);
+            } else if has_extension(path, &PLAYLIST_EXTENSIONS) {
+                let bytes = fs::read(path)
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                let base_dir = path.parent().unwrap_or(Path::new(""));
+                entries.extend(parse_m3u(&decode_text(&bytes), base_dir));
+            } else {
+                entries.push(path.clone());
+            }
+        }
+        Ok(Self::new(entries))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Position of the current song in play order
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn current(&self) -> Option<&Path> {
+        self.order
+            .get(self.position)
+            .map(|&index| self.entries[index].as_path())
+    }
+
+    pub fn shuffle(&self) -> bool {
+        self.shuffle
+    }
+
+    pub fn repeat(&self) -> RepeatMode {
+        self.repeat
+    }
+
+    pub fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.repeat = repeat;
+    }
+
+    pub fn cycle_repeat(&mut self) {
+        self.repeat = self.repeat.cycle();
+    }
+
+    /// Shuffles the songs after the current one, or goes back to the original order
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        self.shuffle = shuffle;
+        let current = self.order.get(self.position).copied();
+        if shuffle {
+            let mut rest: Vec<usize> = (0..self.entries.len())
+                .filter(|&index| Some(index) != current)
+                .collect();
+            rest.shuffle();
+            self.order = current.into_iter().chain(rest).collect();
+            self.position = 0;
+        } else {
+            self.order = (0..self.entries.len()).collect();
+            self.position = current.unwrap_or(0);
+        }
+    }
+
+    /// Makes the song at `index` of the entries the current one
+    pub fn select(&mut self, index: usize) {
+        if let Some(position) = self.order.iter().position(|&entry| entry == index) {
+            self.position = position;
+        }
+    }
+
+    /// Moves to the next song, wrapping around unless repeat is off
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> bool {
+        if self.position + 1 < self.order.len() {
+            self.position += 1;
+            true
+        } else if self.repeat != RepeatMode::Off && !self.order.is_empty() {
+            if self.shuffle {
+                self.order.shuffle();
+            }
+            self.position = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Moves to the previous song, wrapping around unless repeat is off
+    pub fn previous(&mut self) -> bool {
+        if self.position > 0 {
+            self.position -= 1;
+            true
+        } else if self.repeat != RepeatMode::Off && self.order.len() > 1 {
+            self.position = self.order.len() - 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Picks the song to play after the current one ended, `false` when the playlist is over
+    pub fn song_finished(&mut self) -> bool {
+        match self.repeat {
+            RepeatMode::One => !self.order.is_empty(),
+            _ => self.next(),
+        }
+    }
+
+    /// Drops the current song, e.g. when it can't be read, the next song becoming current
+    pub fn remove_current(&mut self) {
+        if self.position >= self.order.len() {
+            return;
+        }
+        let removed = self.order.remove(self.position);
+        self.entries.remove(removed);
+        for index in &mut self.order {
+            if *index > removed {
+                *index -= 1;
+            }
+        }
+        if self.position >= self.order.len() {
+            self.position = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playlist(names: &[&str]) -> Playlist {
+        Playlist::new(names.iter().map(PathBuf::from).collect())
+    }
+
+    fn current(playlist: &Playlist) -> &str {
+        playlist.current().unwrap().to_str().unwrap()
+    }
+
+    #[test]
+    fn test_parse_m3u() {
+        let text =
+            "\u{feff}#EXTM3U\n#EXTINF:123,Nyan Cat\nnyan_cat.nbs\n\n  packs/Mesmerizer.zip \r\n";
+        assert_eq!(
+            parse_m3u(text, Path::new("songs")),
+            vec![
+                Path::new("songs").join("nyan_cat.nbs"),
+                Path::new("songs").join("packs/Mesmerizer.zip"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_next_previous_and_repeat() {
+        let mut songs = playlist(&["a", "b", "c"]);
+        assert!(!songs.previous());
+        assert!(songs.next());
+        assert!(songs.next());
+        assert_eq!(current(&songs), "c");
+        assert!(!songs.next());
+        assert!(!songs.song_finished());
+
+        songs.set_repeat(RepeatMode::One);
+        assert!(songs.song_finished());
+        assert_eq!(current(&songs), "c");
+
+        songs.set_repeat(RepeatMode::All);
+        assert!(songs.song_finished());
+        assert_eq!(current(&songs), "a");
+        assert!(songs.previous());
+        assert_eq!(current(&songs), "c");
+    }
+
+    #[test]
+    fn test_shuffle_keeps_current_song() {
+        let mut songs = playlist(&["a", "b", "c", "d", "e"]);
+        songs.next();
+        songs.set_shuffle(true);
+
+        assert_eq!(current(&songs), "b");
+        let mut order = songs.order.clone();
+        order.sort();
+        assert_eq!(order, vec![0, 1, 2, 3, 4]);
+
+        songs.next();
+        let playing = current(&songs).to_string();
+        songs.set_shuffle(false);
+        assert_eq!(current(&songs), playing);
+    }
+
+    #[test]
+    fn test_remove_current() {
+        let mut songs = playlist(&["a", "b", "c"]);
+        songs.next();
+        songs.remove_current();
+        assert_eq!(current(&songs), "c");
+        songs.remove_current();
+        assert_eq!(current(&songs), "a");
+        songs.remove_current();
+        assert!(songs.is_empty());
+        assert_eq!(songs.current(), None);
+    }
+}
@@ -0,0 +1,192 @@
+use std::{collections::HashMap, f64::consts::PI, sync::Arc};
+
+use kira::Frame;
+
+/// Zero crossings of the sinc kernel on each side of the sample
+const HALF_WIDTH: f64 = 16.0;
+
+/// How pitched notes are rendered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResampleQuality {
+    /// Pitch through kira's playback rate, cheap but aliases on high keys
+    Fast,
+    /// Pre-render every pitched variant with a windowed sinc resampler
+    HighQuality,
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Blackman window over `[-1, 1]`
+fn blackman(x: f64) -> f64 {
+    if x.abs() >= 1.0 {
+        return 0.0;
+    }
+    let phase = PI * (x + 1.0);
+    0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos()
+}
+
+/// Resamples `frames` so they play `ratio` times faster, low-passing when pitching up
+pub fn resample_sinc(frames: &[Frame], ratio: f64) -> Vec<Frame> {
+    if frames.is_empty() || ratio <= 0.0 {
+        return Vec::new();
+    }
+
+    // Lower the cutoff when pitching up so the shifted harmonics don't fold back
+    let cutoff = (1.0 / ratio).min(1.0);
+    let support = HALF_WIDTH / cutoff;
+    let output_len = (frames.len() as f64 / ratio).ceil() as usize;
+
+    let mut output = Vec::with_capacity(output_len);
+    for i in 0..output_len {
+        let center = i as f64 * ratio;
+        let first = ((center - support).ceil().max(0.0)) as usize;
+        let last = ((center + support).floor() as usize).min(frames.len() - 1);
+
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (j, frame) in frames.iter().enumerate().take(last + 1).skip(first) {
+            let distance = j as f64 - center;
+            let weight = cutoff * sinc(cutoff * distance) * blackman(distance / support);
+            left += frame.left as f64 * weight;
+            right += frame.right as f64 * weight;
+        }
+
+        output.push(Frame {
+            left: left as f32,
+            right: right as f32,
+        });
+    }
+
+    output
+}
+
+/// Playback rate for a pitch offset in cents
+pub fn cents_to_ratio(cents: i32) -> f64 {
+    2f64.powf(cents as f64 / 1200.0)
+}
+
+/// Pitched variants of instrument samples, rendered once per instrument and pitch offset
+#[derive(Default)]
+pub struct PitchCache {
+    variants: HashMap<(u32, i32), Arc<[Frame]>>,
+}
+
+impl PitchCache {
+    /// Returns the frames of `instrument` shifted by `cents`, rendering them on first use
+    pub fn get_or_render(&mut self, instrument: u32, cents: i32, frames: &[Frame]) -> Arc<[Frame]> {
+        self.variants
+            .entry((instrument, cents))
+            .or_insert_with(|| resample_sinc(frames, cents_to_ratio(cents)).into())
+            .clone()
+    }
+
+    /// Frames of a variant that was already rendered
+    pub fn get(&self, instrument: u32, cents: i32) -> Option<Arc<[Frame]>> {
+        self.variants.get(&(instrument, cents)).cloned()
+    }
+
+    pub fn contains(&self, instrument: u32, cents: i32) -> bool {
+        self.variants.contains_key(&(instrument, cents))
+    }
+
+    pub fn len(&self) -> usize {
+        self.variants.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.variants.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.variants.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, sample_rate: f64, len: usize) -> Vec<Frame> {
+        (0..len)
+            .map(|i| {
+                let value = (2.0 * PI * frequency * i as f64 / sample_rate).sin() as f32;
+                Frame {
+                    left: value,
+                    right: value,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_resample_length() {
+        let frames = vec![
+            Frame {
+                left: 0.5,
+                right: 0.5
+            };
+            1000
+        ];
+
+        assert_eq!(resample_sinc(&frames, 2.0).len(), 500);
+        assert_eq!(resample_sinc(&frames, 0.5).len(), 2000);
+        assert!(resample_sinc(&[], 2.0).is_empty());
+    }
+
+    #[test]
+    fn test_resample_keeps_dc_level() {
+        let frames = vec![
+            Frame {
+                left: 0.5,
+                right: -0.25
+            };
+            2000
+        ];
+        let output = resample_sinc(&frames, 1.5);
+
+        // Away from the edges a constant signal must stay constant
+        let middle = output[output.len() / 2];
+        assert!((middle.left - 0.5).abs() < 1e-2);
+        assert!((middle.right + 0.25).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_resample_removes_aliasing_content() {
+        // 15 kHz pitched up an octave lands above Nyquist and must be filtered out
+        let frames = sine(15_000.0, 44_100.0, 4096);
+        let output = resample_sinc(&frames, 2.0);
+
+        let middle = &output[output.len() / 4..output.len() * 3 / 4];
+        let peak = middle.iter().map(|f| f.left.abs()).fold(0.0, f32::max);
+        assert!(peak < 0.05, "aliased peak {}", peak);
+    }
+
+    #[test]
+    fn test_pitch_cache_reuses_variants() {
+        let frames = vec![
+            Frame {
+                left: 0.1,
+                right: 0.1
+            };
+            100
+        ];
+        let mut cache = PitchCache::default();
+
+        assert!(!cache.contains(0, 1200));
+        assert!(cache.get(0, 1200).is_none());
+        let first = cache.get_or_render(0, 1200, &frames);
+        assert!(Arc::ptr_eq(&first, &cache.get(0, 1200).unwrap()));
+        let second = cache.get_or_render(0, 1200, &frames);
+        cache.get_or_render(0, -1200, &frames);
+
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(first.len(), 50);
+        assert_eq!(cache.len(), 2);
+    }
+}
@@ -0,0 +1,103 @@
+use std::ops::Range;
+
+use crate::{
+    note::NoteBlock,
+    sink::{NoteEvent, NoteSink},
+};
+
+/// Tracks the next tick of a song to play, so the notes of each tick play once even when a
+/// frame spans several ticks
+#[derive(Debug)]
+pub struct Scheduler {
+    song_length: usize,
+    next_tick: usize,
+}
+
+impl Scheduler {
+    pub fn new(song_length: usize) -> Self {
+        Self {
+            song_length,
+            next_tick: 0,
+        }
+    }
+
+    /// Forgets the played ticks so every note plays again
+    pub fn restart(&mut self) {
+        self.next_tick = 0;
+    }
+
+    /// Jumps to a tick, the ticks before it counting as played
+    pub fn seek(&mut self, tick: usize) {
+        self.next_tick = tick.min(self.song_length);
+    }
+
+    /// Ticks reached by the current position that weren't played yet, marking them as played
+    pub fn due_ticks(&mut self, current_tick: f32) -> Range<usize> {
+        let end = (current_tick.floor() as usize + 1).min(self.song_length);
+        let start = self.next_tick;
+        self.next_tick = self.next_tick.max(end);
+        start..self.next_tick
+    }
+
+    /// Triggers the notes of the due ticks on a sink, returning the ticks
+    pub fn trigger_due(
+        &mut self,
+        current_tick: f32,
+        notes_per_second: f32,
+        note_blocks: &[Vec<NoteBlock>],
+        sink: &mut dyn NoteSink,
+    ) -> Range<usize> {
+        let ticks = self.due_ticks(current_tick);
+        for tick in ticks.clone() {
+            let time = tick as f64 / notes_per_second as f64;
+            for note in note_blocks.get(tick).into_iter().flatten() {
+                sink.trigger_note(&NoteEvent::new(note, tick, time));
+            }
+        }
+        ticks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    #[test]
+    fn test_ticks_play_once() {
+        let mut scheduler = Scheduler::new(4);
+        assert_eq!(scheduler.due_ticks(0.2), 0..1);
+        assert_eq!(scheduler.due_ticks(0.8), 1..1);
+        assert_eq!(scheduler.due_ticks(2.), 1..3);
+        assert_eq!(scheduler.due_ticks(9.), 3..4);
+        assert!(scheduler.due_ticks(9.).is_empty());
+
+        scheduler.seek(1);
+        assert_eq!(scheduler.due_ticks(1.5), 1..2);
+
+        scheduler.restart();
+        assert_eq!(scheduler.due_ticks(0.), 0..1);
+    }
+
+    #[test]
+    fn test_trigger_due_notes() {
+        let note = NoteBlock {
+            was_played: false,
+            instrument: 0,
+            key: 45,
+            velocity: 100,
+            panning: 100,
+            pitch: 0,
+        };
+        let note_blocks = vec![vec![note.clone()], Vec::new(), vec![note.clone(), note]];
+        let mut scheduler = Scheduler::new(note_blocks.len());
+        let mut sink = RecordingSink::default();
+
+        for current_tick in [0., 0.5, 2.] {
+            scheduler.trigger_due(current_tick, 10., &note_blocks, &mut sink);
+        }
+
+        assert_eq!(sink.ticks(), vec![0, 2, 2]);
+        assert_eq!(sink.notes[1].time, 0.2);
+    }
+}
@@ -0,0 +1,414 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+};
+
+use flate2::{Compression, write::GzEncoder};
+use nbs_rs::NbsFile;
+
+use crate::{
+    minecraft::{self, GameVersion},
+    nbt::{self, Tag},
+};
+
+/// Redstone ticks per second, the resolution of repeater delays
+const REDSTONE_TICKS_PER_SECOND: f32 = 10.0;
+/// Longest delay of a single repeater, in redstone ticks
+const MAX_REPEATER_DELAY: u32 = 4;
+/// Redstone dust carries a signal this many blocks
+const DUST_REACH: usize = 15;
+/// Distance between lanes, leaving an empty column so lanes don't power each other
+const LANE_SPACING: usize = 2;
+
+const AIR: &str = "minecraft:air";
+/// Floor under the redstone and filler between notes of a lane
+const FILLER_BLOCK: &str = "minecraft:stone";
+const START_BUTTON: &str = "minecraft:stone_button[face=floor,facing=north,powered=false]";
+const START_DUST: &str =
+    "minecraft:redstone_wire[east=side,north=none,power=0,south=none,west=side]";
+
+pub struct SchematicOptions {
+    pub version: GameVersion,
+}
+
+impl Default for SchematicOptions {
+    fn default() -> Self {
+        Self {
+            version: GameVersion::LATEST,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnplacedReason {
+    /// Custom instruments have no note block equivalent
+    CustomInstrument,
+    /// The instrument doesn't exist in the target version
+    NewerInstrument,
+    /// Another note of the same layer already plays on that tick
+    SlotTaken,
+}
+
+impl UnplacedReason {
+    fn description(self) -> &'static str {
+        match self {
+            UnplacedReason::CustomInstrument => "custom instrument",
+            UnplacedReason::NewerInstrument => "instrument missing from the target version",
+            UnplacedReason::SlotTaken => "another note on the same layer and tick",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UnplacedNote {
+    pub tick: u32,
+    pub layer: u32,
+    pub reason: UnplacedReason,
+}
+
+/// What had to change to build the song out of note blocks
+#[derive(Debug, Default)]
+pub struct SchematicReport {
+    pub lanes: usize,
+    pub placed: usize,
+    /// Notes moved by octaves into the two octaves of a note block
+    pub transposed: usize,
+    pub unplaced: Vec<UnplacedNote>,
+    /// Repeater delay between two song ticks, in redstone ticks
+    pub redstone_ticks_per_tick: u32,
+    /// Ticks per second the build plays at
+    pub played_tempo: f32,
+    /// The song tempo isn't a whole number of redstone ticks per song tick
+    pub uneven_timing: bool,
+    /// Width, height and length of the schematic
+    pub size: (usize, usize, usize),
+}
+
+impl SchematicReport {
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.transposed > 0 {
+            warnings.push(format!(
+                "{} notes were transposed into the note block range",
+                self.transposed
+            ));
+        }
+        if self.uneven_timing {
+            warnings.push(format!(
+                "the build plays at {:.2} ticks per second instead of the song tempo, with {} \
+                 redstone ticks between song ticks",
+                self.played_tempo, self.redstone_ticks_per_tick
+            ));
+        }
+        for reason in [
+            UnplacedReason::CustomInstrument,
+            UnplacedReason::NewerInstrument,
+            UnplacedReason::SlotTaken,
+        ] {
+            let notes: Vec<String> = self
+                .unplaced
+                .iter()
+                .filter(|note| note.reason == reason)
+                .map(|note| format!("tick {} layer {}", note.tick, note.layer + 1))
+                .collect();
+            if !notes.is_empty() {
+                warnings.push(format!(
+                    "{} notes couldn't be placed ({}): {}{}",
+                    notes.len(),
+                    reason.description(),
+                    notes[..notes.len().min(10)].join(", "),
+                    if notes.len() > 10 { ", ..." } else { "" }
+                ));
+            }
+        }
+        warnings
+    }
+}
+
+/// Block grid with its palette, in the Sponge index order
+struct Grid {
+    width: usize,
+    length: usize,
+    blocks: Vec<u32>,
+    palette: Vec<String>,
+    palette_ids: HashMap<String, u32>,
+}
+
+impl Grid {
+    fn new(width: usize, height: usize, length: usize) -> Self {
+        Self {
+            width,
+            length,
+            blocks: vec![0; width * height * length],
+            palette: vec![AIR.to_string()],
+            palette_ids: HashMap::from([(AIR.to_string(), 0)]),
+        }
+    }
+
+    fn set(&mut self, x: usize, y: usize, z: usize, state: &str) {
+        let id = match self.palette_ids.get(state) {
+            Some(&id) => id,
+            None => {
+                let id = self.palette.len() as u32;
+                self.palette.push(state.to_string());
+                self.palette_ids.insert(state.to_string(), id);
+                id
+            }
+        };
+        self.blocks[(y * self.length + z) * self.width + x] = id;
+    }
+
+    /// Places a block on the redstone level with its floor block under it
+    fn place(&mut self, x: usize, z: usize, state: &str, floor: &str) {
+        self.set(x, 0, z, floor);
+        self.set(x, 1, z, state);
+    }
+
+    /// Block ids as unsigned LEB128 varints
+    fn block_data(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.blocks.len());
+        for &id in &self.blocks {
+            let mut id = id;
+            while id >= 0x80 {
+                data.push((id & 0x7F) as u8 | 0x80);
+                id >>= 7;
+            }
+            data.push(id as u8);
+        }
+        data
+    }
+}
+
+fn repeater(delay: u32, facing: &str) -> String {
+    format!(
+        "minecraft:repeater[delay={},facing={},locked=false,powered=false]",
+        delay, facing
+    )
+}
+
+/// Splits a delay over `count` repeaters, each between 1 and 4 redstone ticks
+fn split_delay(total: u32, count: u32) -> Vec<u32> {
+    (0..count)
+        .map(|index| total / count + u32::from(index < total % count))
+        .collect()
+}
+
+/// Builds a Sponge schematic (version 2) playing the song. Each layer is a lane of repeaters
+/// and note blocks running along +Z, started together by the button at the origin.
+pub fn export_schematic(
+    song: &NbsFile,
+    options: &SchematicOptions,
+) -> Result<(Vec<u8>, SchematicReport), String> {
+    let notes_per_second = (song.header.tempo as f32 / 100.0).max(0.01);
+    let exact_delay = REDSTONE_TICKS_PER_SECOND / notes_per_second;
+    let delay = (exact_delay.round() as u32).max(1);
+
+    let mut report = SchematicReport {
+        redstone_ticks_per_tick: delay,
+        played_tempo: REDSTONE_TICKS_PER_SECOND / delay as f32,
+        uneven_timing: (exact_delay - delay as f32).abs() > 1e-3,
+        ..Default::default()
+    };
+
+    // Note block per tick for every layer
+    let mut lanes: BTreeMap<u32, BTreeMap<u32, (&minecraft::VanillaInstrument, u8)>> =
+        BTreeMap::new();
+    let mut notes: Vec<_> = song.notes.iter().collect();
+    notes.sort_by_key(|note| (note.tick as u32, note.layer as u32));
+
+    for note in notes {
+        let (tick, layer) = (note.tick as u32, note.layer as u32);
+        let instrument = match (
+            minecraft::vanilla_instrument(note.instrument, GameVersion::LATEST),
+            minecraft::vanilla_instrument(note.instrument, options.version),
+        ) {
+            _ if note.instrument >= song.header.default_instruments => {
+                Err(UnplacedReason::CustomInstrument)
+            }
+            (None, _) => Err(UnplacedReason::CustomInstrument),
+            (Some(_), None) => Err(UnplacedReason::NewerInstrument),
+            (_, Some(instrument)) => Ok(instrument),
+        };
+        let lane = lanes.entry(layer).or_default();
+        let instrument = instrument.and_then(|instrument| {
+            if lane.contains_key(&tick) {
+                Err(UnplacedReason::SlotTaken)
+            } else {
+                Ok(instrument)
+            }
+        });
+
+        match instrument {
+            Ok(instrument) => {
+                let key = minecraft::transpose_into_range(note.key as i32);
+                if key != note.key {
+                    report.transposed += 1;
+                }
+                lane.insert(tick, (instrument, key - minecraft::MIN_KEY));
+                report.placed += 1;
+            }
+            Err(reason) => report.unplaced.push(UnplacedNote {
+                tick,
+                layer,
+                reason,
+            }),
+        }
+    }
+    lanes.retain(|_, lane| !lane.is_empty());
+    report.lanes = lanes.len();
+
+    // Lane x positions are odd so the start line repeaters, every 16 blocks, sit between lanes
+    let lane_x = |index: usize| 1 + index * LANE_SPACING;
+    let start_repeaters = |x: usize| x / (DUST_REACH + 1);
+    let last_x = lane_x(lanes.len().max(1) - 1);
+    let max_start_delay = start_repeaters(last_x) as u32;
+
+    // Lanes reached later by the start line get less lead-in delay
+    let lead_in = max_start_delay.div_ceil(MAX_REPEATER_DELAY - 1).max(1);
+    let segment = delay.div_ceil(MAX_REPEATER_DELAY);
+    let last_tick = lanes
+        .values()
+        .filter_map(|lane| lane.keys().next_back())
+        .max()
+        .copied()
+        .unwrap_or(0);
+    let slot_z = |tick: u32| (1 + lead_in + tick * (segment + 1)) as usize;
+
+    let (width, height, length) = (last_x + 1, 2, slot_z(last_tick) + 1);
+    if length > u16::MAX as usize || width > u16::MAX as usize {
+        return Err(format!(
+            "The song is too long for a schematic ({} blocks)",
+            length
+        ));
+    }
+    report.size = (width, height, length);
+
+    let mut grid = Grid::new(width, height, length);
+
+    // Start line along +X
+    grid.place(0, 0, START_BUTTON, FILLER_BLOCK);
+    for x in 1..=last_x {
+        if x % (DUST_REACH + 1) == 0 {
+            grid.place(x, 0, &repeater(1, "west"), FILLER_BLOCK);
+        } else {
+            grid.place(x, 0, START_DUST, FILLER_BLOCK);
+        }
+    }
+
+    for (index, lane) in lanes.values().enumerate() {
+        let x = lane_x(index);
+        let lead_delay = lead_in + max_start_delay - start_repeaters(x) as u32;
+        for (offset, delay) in split_delay(lead_delay, lead_in).into_iter().enumerate() {
+            grid.place(x, 1 + offset, &repeater(delay, "north"), FILLER_BLOCK);
+        }
+
+        let lane_end = *lane.keys().next_back().unwrap();
+        for tick in 0..=lane_end {
+            let z = slot_z(tick);
+            match lane.get(&tick) {
+                Some((instrument, note)) => grid.place(
+                    x,
+                    z,
+                    &format!(
+                        "minecraft:note_block[instrument={},note={},powered=false]",
+                        instrument.name, note
+                    ),
+                    instrument.base_block,
+                ),
+                None => grid.place(x, z, FILLER_BLOCK, FILLER_BLOCK),
+            }
+            if tick < lane_end {
+                for (offset, delay) in split_delay(delay, segment).into_iter().enumerate() {
+                    grid.place(x, z + 1 + offset, &repeater(delay, "north"), FILLER_BLOCK);
+                }
+            }
+        }
+    }
+
+    let palette = grid
+        .palette
+        .iter()
+        .enumerate()
+        .map(|(id, state)| (state.clone(), Tag::Int(id as i32)))
+        .collect();
+    let schematic = Tag::Compound(vec![
+        ("Version".to_string(), Tag::Int(2)),
+        (
+            "DataVersion".to_string(),
+            Tag::Int(options.version.data_version()),
+        ),
+        ("Width".to_string(), Tag::Short(width as u16 as i16)),
+        ("Height".to_string(), Tag::Short(height as u16 as i16)),
+        ("Length".to_string(), Tag::Short(length as u16 as i16)),
+        ("Offset".to_string(), Tag::IntArray(vec![0, 0, 0])),
+        (
+            "PaletteMax".to_string(),
+            Tag::Int(grid.palette.len() as i32),
+        ),
+        ("Palette".to_string(), Tag::Compound(palette)),
+        ("BlockData".to_string(), Tag::ByteArray(grid.block_data())),
+        ("BlockEntities".to_string(), Tag::List(Vec::new())),
+    ]);
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(&nbt::write_root("Schematic", &schematic))
+        .and_then(|_| encoder.finish())
+        .map(|bytes| (bytes, report))
+        .map_err(|e| format!("Failed to compress the schematic: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    #[test]
+    fn test_split_delay() {
+        assert_eq!(split_delay(5, 2), [3, 2]);
+        assert_eq!(split_delay(4, 1), [4]);
+        assert_eq!(split_delay(1, 1), [1]);
+    }
+
+    #[test]
+    fn test_export_schematic() {
+        let nbs_data = include_bytes!("../test-assets/nyan_cat.nbs") as &[u8];
+        let song = crate::song::load_nbs_file(Some(nbs_data)).song;
+
+        let (bytes, report) = export_schematic(&song, &SchematicOptions::default()).unwrap();
+        assert_eq!(report.placed + report.unplaced.len(), song.notes.len());
+        assert!(report.lanes <= song.layers.len().max(1));
+
+        let mut nbt = Vec::new();
+        GzDecoder::new(bytes.as_slice())
+            .read_to_end(&mut nbt)
+            .unwrap();
+        assert_eq!(&nbt[..12], b"\x0a\x00\x09Schematic");
+
+        let (width, height, length) = report.size;
+        assert!(width * height * length <= nbt.len());
+    }
+
+    #[test]
+    fn test_custom_instruments_follow_the_song_vanilla_count() {
+        let nbs_data = include_bytes!("../test-assets/nyan_cat.nbs") as &[u8];
+        let mut song = crate::song::load_nbs_file(Some(nbs_data)).song;
+        song.header.default_instruments = 10;
+
+        let (_, report) = export_schematic(&song, &SchematicOptions::default()).unwrap();
+        assert_eq!(
+            report
+                .unplaced
+                .iter()
+                .filter(|note| note.reason == UnplacedReason::CustomInstrument)
+                .count(),
+            song.notes
+                .iter()
+                .filter(|note| note.instrument >= 10)
+                .count()
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use crate::note::NoteBlock;
+
+/// A note triggered by the scheduler
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoteEvent {
+    /// Tick the note belongs to
+    pub tick: usize,
+    /// Seconds since the start of the song, at the song's tempo
+    pub time: f64,
+    pub instrument: u8,
+    pub key: u8,
+    /// Volume from 0 to 100, layer volume included
+    pub velocity: u8,
+    /// Stereo position from 0 (left) to 200 (right), 100 being the center
+    pub panning: u8,
+    /// Fine pitch in cents
+    pub pitch: i16,
+}
+
+impl NoteEvent {
+    pub fn new(note: &NoteBlock, tick: usize, time: f64) -> Self {
+        Self {
+            tick,
+            time,
+            instrument: note.instrument,
+            key: note.key,
+            velocity: note.velocity,
+            panning: note.panning,
+            pitch: note.pitch,
+        }
+    }
+
+    /// Stereo position from -1 (left) to 1 (right)
+    pub fn stereo_position(&self) -> f32 {
+        self.panning as f32 / 100. - 1.
+    }
+}
+
+/// Output the scheduler triggers notes on: speakers, a rendered buffer, a MIDI file or a
+/// recording
+pub trait NoteSink {
+    fn trigger_note(&mut self, note: &NoteEvent);
+
+    /// Cuts the notes still ringing, e.g. when seeking or changing songs
+    fn stop_all(&mut self) {}
+}
+
+/// Keeps every triggered note, so tests can check what was played and when
+#[derive(Debug, Default)]
+pub struct RecordingSink {
+    pub notes: Vec<NoteEvent>,
+    /// How many times the notes were cut
+    pub stops: usize,
+}
+
+impl RecordingSink {
+    /// Ticks of the recorded notes, in the order they were triggered
+    pub fn ticks(&self) -> Vec<usize> {
+        self.notes.iter().map(|note| note.tick).collect()
+    }
+}
+
+impl NoteSink for RecordingSink {
+    fn trigger_note(&mut self, note: &NoteEvent) {
+        self.notes.push(*note);
+    }
+
+    fn stop_all(&mut self) {
+        self.stops += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn panned(panning: u8) -> NoteEvent {
+        NoteEvent {
+            tick: 0,
+            time: 0.,
+            instrument: 0,
+            key: 45,
+            velocity: 100,
+            panning,
+            pitch: 0,
+        }
+    }
+
+    #[test]
+    fn test_right_of_center_plays_on_the_right() {
+        assert_eq!(panned(100).stereo_position(), 0.);
+        assert!(panned(150).stereo_position() > 0.);
+        assert_eq!(panned(200).stereo_position(), 1.);
+        assert_eq!(panned(0).stereo_position(), -1.);
+    }
+}
@@ -1,93 +1,285 @@
-use std::io::Read;
+#[cfg(feature = "zip")]
+use std::io::{Cursor, Read};
+use std::path::Path;
 
 use log;
 use nbs_rs::{NbsFile, NbsParser};
+#[cfg(feature = "zip")]
+use zip::ZipArchive;
+
+use crate::{
+    format::{self, FormatInfo, SongSource},
+    midi, pack,
+    utils::decode_text,
+};
 
 /// Determine whether to load from a ZIP or a normal file
 fn is_zip_file(bytes: &[u8]) -> bool {
     bytes.starts_with(&[0x50, 0x4B, 0x03, 0x04])
 }
 
-pub struct SongData<'a> {
-    pub song: NbsFile,
-    pub extra_sounds: Vec<(&'a [u8], f64)>,
+/// Determine whether the bytes are a Standard MIDI File
+fn is_midi_file(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"MThd")
 }
 
-/// Load an NBS file from a ZIP archive
-fn load_nbs_from_zip<'a>(bytes: &'a [u8]) -> SongData<'a> {
-    log::info!("Loading song from ZIP file, with {:?} bytes", bytes.len());
+/// Settings for formats that are converted to NBS when loaded
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LoadOptions {
+    pub midi: midi::MidiImportOptions,
+}
 
-    let mut zip = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+/// Song played when none is given, and its file name
+#[cfg(feature = "zip")]
+const DEFAULT_SONG: &[u8] = include_bytes!("../test-assets/bo en - My Time.zip");
+#[cfg(feature = "zip")]
+const DEFAULT_SONG_FILE_NAME: &str = "bo en - My Time.zip";
+#[cfg(not(feature = "zip"))]
+const DEFAULT_SONG: &[u8] = include_bytes!("../test-assets/nyan_cat.nbs");
+#[cfg(not(feature = "zip"))]
+const DEFAULT_SONG_FILE_NAME: &str = "nyan_cat.nbs";
 
-    // Extract NBS song
-    let nbs_data = {
-        let mut nbs_file = zip.by_name("song.nbs").unwrap();
-        let mut data = Vec::new();
-        nbs_file.read_to_end(&mut data).unwrap();
-        data
-    };
+/// Error for song packs when the crate is built without ZIP support
+#[cfg(not(feature = "zip"))]
+const ZIP_DISABLED: &str = "ZIP song packs need the zip feature";
+
+pub struct SongData {
+    pub song: NbsFile,
+    /// Sound and base key of each custom instrument of a song pack, `None` for those missing
+    /// from the pack
+    pub extra_sounds: Vec<Option<(Vec<u8>, f64)>>,
+    pub format: FormatInfo,
+    /// Name of the file the song was loaded from, when known
+    pub file_name: Option<String>,
+    /// Name of the NBS file inside a ZIP song pack
+    pub inner_name: Option<String>,
+}
+
+/// File name without its folders and extension, for Windows and Unix paths
+fn file_stem(path: &str) -> &str {
+    let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    match name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => name,
+    }
+}
 
-    let mut song_parser = NbsParser::new(&nbs_data);
-    let song = song_parser.parse().unwrap();
+/// Credits line from an author and an original author, whichever are set
+pub fn credits(author: &str, original_author: &str) -> Option<String> {
+    let (author, original_author) = (author.trim(), original_author.trim());
+    match (author.is_empty(), original_author.is_empty()) {
+        (true, true) => None,
+        (false, true) => Some(author.to_string()),
+        (true, false) => Some(format!("original by {}", original_author)),
+        (false, false) if author == original_author => Some(author.to_string()),
+        (false, false) => Some(format!("{}, original by {}", author, original_author)),
+    }
+}
 
-    let instruments = &song.instruments;
+impl SongData {
+    /// Song name, falling back to the pack entry, file and imported file names
+    pub fn title(&self) -> String {
+        let song_name = decode_text(&self.song.header.song_name);
+        let imported_file = decode_text(&self.song.header.song_origin);
+        let inner_name = self
+            .inner_name
+            .as_deref()
+            .filter(|name| *name != pack::PACK_SONG_NAME);
 
-    // Owned storage for sound data
-    let mut sounds_storage: Vec<(Vec<u8>, f64)> = Vec::new();
-    let mut extra_sounds: Vec<(&[u8], f64)> = Vec::new();
+        [
+            Some(song_name.trim()),
+            inner_name.map(file_stem),
+            self.file_name.as_deref().map(file_stem),
+            Some(file_stem(imported_file.trim())),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|title| !title.is_empty())
+        .unwrap_or("Untitled")
+        .to_string()
+    }
 
-    for instrument in instruments {
-        let sound_name = format!(
-            "sounds/{}",
-            String::from_utf8(instrument.name.clone()).unwrap()
-        );
+    /// Author and original author, whichever are set
+    pub fn credits(&self) -> Option<String> {
+        credits(
+            &decode_text(&self.song.header.song_author),
+            &decode_text(&self.song.header.original_author),
+        )
+    }
 
-        if let Ok(mut sound_file) = zip.by_name(&sound_name) {
-            let mut sound = Vec::new();
-            sound_file.read_to_end(&mut sound).unwrap();
-            let key = instrument.key as f64;
-            sounds_storage.push((sound, key)); // Store owned data
+    /// Title with the credits when there are some
+    pub fn full_title(&self) -> String {
+        match self.credits() {
+            Some(credits) => format!("{} - {}", self.title(), credits),
+            None => self.title(),
         }
     }
+}
 
-    // Borrow slices from owned data
-    for sound in &sounds_storage {
-        extra_sounds.push((sound.0.as_slice(), sound.1));
-    }
+/// Parses NBS bytes, filling in the fields their version doesn't have
+fn try_parse_nbs(bytes: &[u8], source: SongSource) -> Result<(NbsFile, FormatInfo), String> {
+    let format = FormatInfo::detect(bytes, source);
+    log::info!("Song format: {}", format.describe());
 
-    // Leak sounds_storage to extend its lifetime
-    let leaked_sounds_storage = Box::leak(Box::new(sounds_storage));
+    let mut song = NbsParser::new(bytes)
+        .parse()
+        .map_err(|e| format!("Failed to parse song: {:?}", e))?;
+    format::apply_defaults(&mut song, &format);
+    Ok((song, format))
+}
 
-    SongData {
-        song,
-        extra_sounds: leaked_sounds_storage
-            .iter()
-            .map(|s| (s.0.as_slice(), s.1))
-            .collect(),
+/// Name and bytes of the NBS song in a ZIP archive
+#[cfg(feature = "zip")]
+fn read_pack_song(zip: &mut ZipArchive<Cursor<&[u8]>>) -> Result<(String, Vec<u8>), String> {
+    // Packs not made by this player may name the song differently
+    let inner_name = if zip.index_for_name(pack::PACK_SONG_NAME).is_some() {
+        pack::PACK_SONG_NAME.to_string()
+    } else {
+        zip.file_names()
+            .find(|name| name.to_lowercase().ends_with(".nbs"))
+            .ok_or("No NBS song in the ZIP file")?
+            .to_string()
+    };
+    let mut data = Vec::new();
+    zip.by_name(&inner_name)
+        .and_then(|mut file| Ok(file.read_to_end(&mut data)?))
+        .map_err(|e| format!("Failed to read {}: {}", inner_name, e))?;
+    Ok((inner_name, data))
+}
+
+/// Load an NBS file from a ZIP archive, reading its custom sounds when `with_sounds`
+#[cfg(feature = "zip")]
+fn load_nbs_from_zip(bytes: &[u8], with_sounds: bool) -> Result<SongData, String> {
+    log::info!("Loading song from ZIP file, with {:?} bytes", bytes.len());
+
+    let mut zip = ZipArchive::new(Cursor::new(bytes))
+        .map_err(|e| format!("Failed to open the ZIP file: {}", e))?;
+    let (inner_name, nbs_data) = read_pack_song(&mut zip)?;
+
+    let (song, format) = try_parse_nbs(&nbs_data, SongSource::Pack)?;
+
+    let mut extra_sounds: Vec<Option<(Vec<u8>, f64)>> = Vec::new();
+
+    if with_sounds {
+        for instrument in &song.instruments {
+            let sound_name = pack::pack_sound_path(&pack::pack_sound_name(
+                &decode_text(&instrument.file),
+                &decode_text(&instrument.name),
+            ));
+
+            if let Ok(mut sound_file) = zip.by_name(&sound_name)
+                && sound_file.is_file()
+            {
+                let mut sound = Vec::new();
+                sound_file
+                    .read_to_end(&mut sound)
+                    .map_err(|e| format!("Failed to read {}: {}", sound_name, e))?;
+                let key = instrument.key as f64;
+                extra_sounds.push(Some((sound, key)));
+            } else {
+                extra_sounds.push(None);
+            }
+        }
     }
+
+    Ok(SongData {
+        song,
+        extra_sounds,
+        format,
+        file_name: None,
+        inner_name: Some(inner_name),
+    })
+}
+
+#[cfg(not(feature = "zip"))]
+fn load_nbs_from_zip(_bytes: &[u8], _with_sounds: bool) -> Result<SongData, String> {
+    Err(ZIP_DISABLED.to_string())
 }
+
 /// Load an NBS file directly (not from ZIP)
-fn load_nbs_from_file<'a>(bytes: &'a [u8]) -> SongData<'a> {
+fn load_nbs_from_file(bytes: &[u8]) -> Result<SongData, String> {
     log::info!("Loading song from NBS file, with {:?} bytes", bytes.len());
 
-    let mut song_parser = NbsParser::new(bytes);
-    let song = song_parser.parse().unwrap();
+    let (song, format) = try_parse_nbs(bytes, SongSource::Nbs)?;
 
-    SongData {
+    Ok(SongData {
         song,
         extra_sounds: Vec::new(),
-    }
+        format,
+        file_name: None,
+        inner_name: None,
+    })
 }
 
-pub fn load_nbs_file<'a>(song_data: Option<&'a [u8]>) -> SongData<'a> {
-    let song_data_bytes =
-        song_data.unwrap_or_else(|| include_bytes!("../test-assets/bo en - My Time.zip"));
+/// Convert a MIDI file to NBS and load it
+fn load_nbs_from_midi(bytes: &[u8], options: &midi::MidiImportOptions) -> Result<SongData, String> {
+    log::info!("Loading song from MIDI file, with {:?} bytes", bytes.len());
+
+    let (nbs_data, report) = midi::import_midi(bytes, options)?;
+    for warning in report.warnings() {
+        log::warn!("{}", warning);
+    }
+
+    let (song, format) = try_parse_nbs(&nbs_data, SongSource::Midi)?;
+
+    Ok(SongData {
+        song,
+        extra_sounds: Vec::new(),
+        format,
+        file_name: None,
+        inner_name: None,
+    })
+}
 
-    if is_zip_file(song_data_bytes) {
-        load_nbs_from_zip(song_data_bytes)
+/// Loads a song from ZIP, MIDI or NBS bytes, reading the sounds of packs when `with_sounds`
+fn load_nbs_bytes(
+    bytes: &[u8],
+    options: &LoadOptions,
+    with_sounds: bool,
+) -> Result<SongData, String> {
+    if is_zip_file(bytes) {
+        load_nbs_from_zip(bytes, with_sounds)
+    } else if is_midi_file(bytes) {
+        load_nbs_from_midi(bytes, &options.midi)
     } else {
-        load_nbs_from_file(song_data_bytes)
+        load_nbs_from_file(bytes)
+    }
+}
+
+pub fn load_nbs_file(song_data: Option<&[u8]>) -> SongData {
+    load_nbs_file_with_options(song_data, &LoadOptions::default())
+}
+
+pub fn load_nbs_file_with_options(song_data: Option<&[u8]>, options: &LoadOptions) -> SongData {
+    let song_data_bytes = song_data.unwrap_or(DEFAULT_SONG);
+
+    let mut loaded = load_nbs_bytes(song_data_bytes, options, true).unwrap();
+    if song_data.is_none() {
+        loaded.file_name = Some(DEFAULT_SONG_FILE_NAME.to_string());
     }
+    loaded
+}
+
+/// Loads a song read from `path`, keeping its file name for the title
+pub fn load_song_file(
+    bytes: &[u8],
+    path: &Path,
+    options: &LoadOptions,
+) -> Result<SongData, String> {
+    let mut song_data = load_nbs_bytes(bytes, options, true)?;
+    song_data.file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned());
+    Ok(song_data)
+}
+
+/// Reads a song without the sounds of packs, e.g. to list it
+pub fn load_song_metadata(bytes: &[u8], path: &Path) -> Result<SongData, String> {
+    let mut song_data = load_nbs_bytes(bytes, &LoadOptions::default(), false)?;
+    song_data.file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned());
+    Ok(song_data)
 }
 
 #[cfg(test)]
@@ -103,29 +295,109 @@ mod tests {
         assert!(!is_zip_file(&not_zip));
     }
 
+    #[test]
+    fn test_is_midi_file() {
+        assert!(is_midi_file(b"MThd\x00\x00\x00\x06"));
+        assert!(!is_midi_file(&[0x50, 0x4B, 0x03, 0x04]));
+    }
+
+    #[test]
+    fn test_file_stem() {
+        assert_eq!(file_stem("C:\\Songs\\Megalovania.mid"), "Megalovania");
+        assert_eq!(file_stem("songs/nyan_cat.nbs"), "nyan_cat");
+        assert_eq!(file_stem(".nbs"), ".nbs");
+        assert_eq!(file_stem(""), "");
+    }
+
+    #[test]
+    fn test_title_falls_back_to_file_name() {
+        let nbs_data = include_bytes!("../test-assets/nyan_cat.nbs") as &[u8];
+        let mut song_data = load_song_file(
+            nbs_data,
+            Path::new("test-assets/nyan_cat.nbs"),
+            &LoadOptions::default(),
+        )
+        .unwrap();
+        song_data.song.header.song_name = Vec::new();
+        song_data.song.header.song_author = Vec::new();
+        song_data.song.header.original_author = Vec::new();
+
+        assert_eq!(song_data.title(), "nyan_cat");
+        assert_eq!(song_data.credits(), None);
+        assert_eq!(song_data.full_title(), "nyan_cat");
+    }
+
+    #[test]
+    #[cfg(feature = "zip")]
+    fn test_load_song_metadata() {
+        let pack = include_bytes!("../test-assets/Mesmerizer.zip") as &[u8];
+        let song_data = load_song_metadata(pack, Path::new("Mesmerizer.zip")).unwrap();
+
+        assert!(song_data.extra_sounds.is_empty());
+        assert!(song_data.inner_name.is_some());
+        assert!(
+            load_song_metadata(&[0x50, 0x4B, 0x03, 0x04, 0x00], Path::new("broken.zip")).is_err()
+        );
+    }
+
+    #[test]
+    fn test_load_song_file_reports_errors() {
+        let options = LoadOptions::default();
+
+        assert!(
+            load_song_file(&[0x50, 0x4B, 0x03, 0x04], Path::new("broken.zip"), &options).is_err()
+        );
+        assert!(load_song_file(b"MThd", Path::new("broken.mid"), &options).is_err());
+    }
+
     #[test]
     fn test_load_nbs_from_file() {
-        // Mock simple NBS file data
         let nbs_data = include_bytes!("../test-assets/nyan_cat.nbs") as &[u8];
-        let song_data = load_nbs_from_file(&nbs_data);
+        let song_data = load_nbs_from_file(nbs_data).unwrap();
 
-        assert!(!song_data.extra_sounds.is_empty() || song_data.extra_sounds.is_empty()); // Ensure it runs
+        assert!(!song_data.song.notes.is_empty());
+        assert!(song_data.extra_sounds.is_empty());
+        assert_eq!(song_data.format.source, SongSource::Nbs);
+        assert_eq!(song_data.inner_name, None);
+        assert!(load_nbs_from_file(&nbs_data[..nbs_data.len() / 2]).is_err());
     }
 
     #[test]
     fn test_load_nbs_file() {
         let nbs_data = include_bytes!("../test-assets/nyan_cat.nbs") as &[u8];
-        let song_data = load_nbs_file(Some(&nbs_data));
+        let song_data = load_nbs_file(Some(nbs_data));
+        assert!(!song_data.song.notes.is_empty());
+        assert_eq!(song_data.file_name, None);
 
-        assert!(!song_data.extra_sounds.is_empty() || song_data.extra_sounds.is_empty()); // Ensure it runs
+        // The bundled song is played when none is given
+        let default_song = load_nbs_file(None);
+        assert!(!default_song.song.notes.is_empty());
+        assert_eq!(
+            default_song.file_name.as_deref(),
+            Some(DEFAULT_SONG_FILE_NAME)
+        );
     }
 
     #[test]
+    #[cfg(feature = "zip")]
     fn test_load_nbs_from_zip() {
         let zip_data = include_bytes!("../test-assets/Mesmerizer.zip").to_vec();
 
-        let song_data = load_nbs_from_zip(&zip_data);
+        let song_data = load_nbs_from_zip(&zip_data, true).unwrap();
 
-        assert!(!song_data.extra_sounds.len() > 0);
+        // Every custom instrument of this pack has its sound inside it
+        assert!(!song_data.song.instruments.is_empty());
+        assert_eq!(
+            song_data.extra_sounds.len(),
+            song_data.song.instruments.len()
+        );
+        assert!(
+            song_data
+                .extra_sounds
+                .iter()
+                .all(|sound| sound.as_ref().is_some_and(|(data, _)| !data.is_empty()))
+        );
+        assert_eq!(song_data.format.source, SongSource::Pack);
+        assert!(song_data.inner_name.is_some());
     }
 }
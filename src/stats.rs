@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+
+use nbs_rs::NbsFile;
+use serde::{Deserialize, Serialize};
+
+use crate::{minecraft, utils::decode_text};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InstrumentCount {
+    pub instrument: u8,
+    /// Vanilla sound name, or the custom instrument name
+    pub name: String,
+    pub notes: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LayerCount {
+    pub layer: u32,
+    pub notes: usize,
+}
+
+/// Figures computed from the notes of a song
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SongStats {
+    pub notes: usize,
+    pub notes_per_instrument: Vec<InstrumentCount>,
+    pub notes_per_layer: Vec<LayerCount>,
+    pub lowest_key: Option<u8>,
+    pub highest_key: Option<u8>,
+    /// Most notes starting on the same tick
+    pub max_polyphony: usize,
+    pub max_polyphony_tick: u32,
+    /// Song length in ticks, at least up to the last note
+    pub length_ticks: u32,
+    pub duration_seconds: f32,
+}
+
+/// Name of an instrument id, custom instruments following the vanilla ones the song was saved with
+pub fn instrument_name(song: &NbsFile, instrument: u8) -> String {
+    let first_custom = song.header.default_instruments;
+    let name = if instrument < first_custom {
+        minecraft::INSTRUMENTS
+            .get(instrument as usize)
+            .map(|vanilla| vanilla.name.to_string())
+    } else {
+        song.instruments
+            .get((instrument - first_custom) as usize)
+            .map(|custom| decode_text(&custom.name))
+    };
+    name.unwrap_or_else(|| format!("unknown ({})", instrument))
+}
+
+pub fn compute(song: &NbsFile) -> SongStats {
+    let mut per_instrument: BTreeMap<u8, usize> = BTreeMap::new();
+    let mut per_layer: BTreeMap<u32, usize> = BTreeMap::new();
+    let mut per_tick: BTreeMap<u32, usize> = BTreeMap::new();
+
+    for note in &song.notes {
+        *per_instrument.entry(note.instrument).or_default() += 1;
+        *per_layer.entry(note.layer as u32).or_default() += 1;
+        *per_tick.entry(note.tick as u32).or_default() += 1;
+    }
+
+    let (max_polyphony_tick, max_polyphony) = per_tick
+        .iter()
+        .max_by_key(|&(tick, count)| (*count, std::cmp::Reverse(*tick)))
+        .map(|(&tick, &count)| (tick, count))
+        .unwrap_or((0, 0));
+    let last_tick = per_tick.keys().next_back().map_or(0, |tick| tick + 1);
+    let length_ticks = (song.header.song_length as u32).max(last_tick);
+    let notes_per_second = (song.header.tempo as f32 / 100.0).max(0.01);
+
+    SongStats {
+        notes: song.notes.len(),
+        notes_per_instrument: per_instrument
+            .into_iter()
+            .map(|(instrument, notes)| InstrumentCount {
+                instrument,
+                name: instrument_name(song, instrument),
+                notes,
+            })
+            .collect(),
+        notes_per_layer: per_layer
+            .into_iter()
+            .map(|(layer, notes)| LayerCount { layer, notes })
+            .collect(),
+        lowest_key: song.notes.iter().map(|note| note.key).min(),
+        highest_key: song.notes.iter().map(|note| note.key).max(),
+        max_polyphony,
+        max_polyphony_tick,
+        length_ticks,
+        duration_seconds: length_ticks as f32 / notes_per_second,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_stats() {
+        let nbs_data = include_bytes!("../test-assets/nyan_cat.nbs") as &[u8];
+        let song = crate::song::load_nbs_file(Some(nbs_data)).song;
+        let stats = compute(&song);
+
+        assert_eq!(stats.notes, song.notes.len());
+        assert_eq!(
+            stats
+                .notes_per_instrument
+                .iter()
+                .map(|count| count.notes)
+                .sum::<usize>(),
+            song.notes.len()
+        );
+        assert_eq!(
+            stats
+                .notes_per_layer
+                .iter()
+                .map(|count| count.notes)
+                .sum::<usize>(),
+            song.notes.len()
+        );
+        assert!(stats.lowest_key <= stats.highest_key);
+        assert!(stats.max_polyphony >= 1);
+        assert!(stats.length_ticks > song.notes.iter().map(|n| n.tick as u32).max().unwrap());
+    }
+
+    #[test]
+    fn test_custom_instruments_follow_the_song_vanilla_count() {
+        let nbs_data = include_bytes!("../test-assets/nyan_cat.nbs") as &[u8];
+        let mut song = crate::song::load_nbs_file(Some(nbs_data)).song;
+        assert_eq!(song.header.default_instruments, 16);
+        assert_eq!(instrument_name(&song, 10), "iron_xylophone");
+        assert_eq!(instrument_name(&song, 16), "entity.experience_orb.pickup");
+
+        song.header.default_instruments = 10;
+        assert_eq!(instrument_name(&song, 9), "xylophone");
+        assert_eq!(instrument_name(&song, 10), "entity.experience_orb.pickup");
+        assert_eq!(instrument_name(&song, 13), "unknown (13)");
+    }
+}
@@ -0,0 +1,118 @@
+/// Playback controls, sent by the keyboard and by the page embedding the web build
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransportCommand {
+    Play,
+    Pause,
+    /// Pauses or resumes, starting over when the song has ended
+    TogglePause,
+    /// Jumps to a position in seconds
+    Seek(f32),
+    /// Moves by a number of seconds, backwards when negative
+    SeekBy(f32),
+    /// Note volume from 0 to 1
+    SetVolume(f32),
+    /// Playback speed, 1 being the song's tempo
+    SetSpeed(f32),
+    Next,
+    Previous,
+}
+
+pub const MIN_SPEED: f32 = 0.25;
+pub const MAX_SPEED: f32 = 4.0;
+
+/// Position of the song being played
+#[derive(Clone, Copy, Debug)]
+pub struct Transport {
+    /// Elapsed time in seconds, at the song's tempo
+    pub elapsed_time: f32,
+    pub is_paused: bool,
+    pub speed: f32,
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Self {
+            elapsed_time: 0.,
+            is_paused: true,
+            speed: 1.,
+        }
+    }
+}
+
+impl Transport {
+    pub fn is_end(&self, duration: f32) -> bool {
+        self.elapsed_time >= duration
+    }
+
+    /// Moves the song forward by a frame, scaled by the playback speed
+    pub fn advance(&mut self, delta_time: f32, duration: f32) {
+        if !self.is_paused && !self.is_end(duration) {
+            self.elapsed_time = (self.elapsed_time + delta_time * self.speed).min(duration);
+        }
+    }
+
+    /// Starts a new song from its beginning, keeping the speed
+    pub fn start(&mut self) {
+        self.elapsed_time = 0.;
+        self.is_paused = false;
+    }
+
+    /// Clamped position to seek to from a command, `None` for the other commands
+    pub fn seek_target(&self, command: TransportCommand, duration: f32) -> Option<f32> {
+        match command {
+            TransportCommand::Seek(seconds) => Some(seconds.clamp(0., duration)),
+            TransportCommand::SeekBy(seconds) => {
+                Some((self.elapsed_time + seconds).clamp(0., duration))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        if speed.is_finite() {
+            self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance_stops_at_the_end() {
+        let mut transport = Transport::default();
+        transport.advance(1., 10.);
+        assert_eq!(transport.elapsed_time, 0.);
+
+        transport.start();
+        transport.set_speed(2.);
+        transport.advance(1., 10.);
+        assert_eq!(transport.elapsed_time, 2.);
+        transport.advance(100., 10.);
+        assert!(transport.is_end(10.));
+    }
+
+    #[test]
+    fn test_seek_and_speed_are_clamped() {
+        let mut transport = Transport {
+            elapsed_time: 3.,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            transport.seek_target(TransportCommand::SeekBy(-5.), 10.),
+            Some(0.)
+        );
+        assert_eq!(
+            transport.seek_target(TransportCommand::Seek(20.), 10.),
+            Some(10.)
+        );
+        assert_eq!(transport.seek_target(TransportCommand::Play, 10.), None);
+
+        transport.set_speed(100.);
+        assert_eq!(transport.speed, MAX_SPEED);
+        transport.set_speed(f32::NAN);
+        assert_eq!(transport.speed, MAX_SPEED);
+    }
+}
@@ -4,3 +4,78 @@ pub fn time_formatter(time: f32) -> String {
     let seconds = (time % 60.0) as u32;
     format!("{:0>2}:{:0>2}", minutes, seconds)
 }
+
+/// Characters of Windows-1252 bytes 0x80 to 0x9F, the undefined ones kept as Latin-1 controls
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+];
+
+/// Decodes text stored in a song. Old NBS files were saved in the Windows code page rather than
+/// UTF-8, so text that isn't valid UTF-8 is read as Windows-1252.
+pub fn decode_text(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes
+            .iter()
+            .map(|&byte| match byte {
+                0x80..=0x9F => WINDOWS_1252_HIGH[(byte - 0x80) as usize],
+                _ => byte as char,
+            })
+            .collect(),
+    }
+}
+
+/// Splits text into lines no wider than `max_width` as measured by `measure`, breaking at spaces
+/// and keeping the text's own line breaks. Words wider than a line get a line of their own.
+pub fn wrap_text(text: &str, max_width: f32, measure: impl Fn(&str) -> f32) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() {
+                word.to_string()
+            } else {
+                format!("{} {}", line, word)
+            };
+            if line.is_empty() || measure(&candidate) <= max_width {
+                line = candidate;
+            } else {
+                lines.push(std::mem::replace(&mut line, word.to_string()));
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_text() {
+        assert_eq!(decode_text("Café ♪".as_bytes()), "Café ♪");
+        assert_eq!(decode_text(b"Caf\xe9 \x93Song\x94"), "Café “Song”");
+        assert_eq!(decode_text(b""), "");
+    }
+
+    #[test]
+    fn test_wrap_text() {
+        let measure = |text: &str| text.len() as f32;
+
+        assert_eq!(
+            wrap_text("the quick brown fox", 10.0, measure),
+            ["the quick", "brown fox"]
+        );
+        assert_eq!(
+            wrap_text("first\n\nsecond", 10.0, measure),
+            ["first", "", "second"]
+        );
+        assert_eq!(
+            wrap_text("a incomprehensibilities b", 5.0, measure),
+            ["a", "incomprehensibilities", "b"]
+        );
+    }
+}
@@ -0,0 +1,302 @@
+// Functions exported to the page embedding the wasm build: the page copies a song into memory
+// given by `nbs_alloc`, then hands it over with `nbs_load_song`, the player loading it on its
+// next frame. Controls are queued as transport commands, like the keyboard's, and the player
+// publishes its status every frame for the page to read.
+
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::{song::SongData, stats, transport::TransportCommand, utils::decode_text};
+
+/// Checked by the miniquad JS bundle against the version of the page's plugin
+const WEB_API_VERSION: u32 = 1;
+
+/// A song sent by the page, waiting for the player to load it
+pub struct PendingSong {
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Playback state published by the player every frame
+#[derive(Clone, Copy, Debug)]
+pub struct PlayerStatus {
+    /// Seconds since the start of the song
+    pub position: f32,
+    pub duration: f32,
+    pub tick: f32,
+    pub paused: bool,
+    pub speed: f32,
+    pub volume: f32,
+}
+
+/// Song details sent to the page as JSON
+#[derive(Debug, Serialize)]
+struct SongMetadata {
+    title: String,
+    author: String,
+    original_author: String,
+    description: String,
+    /// Ticks per second
+    tempo: f32,
+    length_ticks: u32,
+    duration: f32,
+    layers: usize,
+    notes: usize,
+    format: String,
+}
+
+static PENDING_SONG: Mutex<Option<PendingSong>> = Mutex::new(None);
+static COMMANDS: Mutex<Vec<TransportCommand>> = Mutex::new(Vec::new());
+static STATUS: Mutex<PlayerStatus> = Mutex::new(PlayerStatus {
+    position: 0.,
+    duration: 0.,
+    tick: 0.,
+    paused: true,
+    speed: 1.,
+    volume: 1.,
+});
+static METADATA: Mutex<String> = Mutex::new(String::new());
+
+#[cfg(target_arch = "wasm32")]
+unsafe extern "C" {
+    /// Provided by the page through a miniquad plugin
+    fn nbs_on_tick(tick: u32);
+    fn nbs_on_end();
+}
+
+/// Queues a song, replacing one the player hasn't loaded yet
+pub fn queue_song(song: PendingSong) {
+    *PENDING_SONG.lock().unwrap() = Some(song);
+}
+
+/// Song sent by the page since the last call
+pub fn take_pending_song() -> Option<PendingSong> {
+    PENDING_SONG.lock().unwrap().take()
+}
+
+/// Commands sent by the page since the last call
+pub fn take_commands() -> Vec<TransportCommand> {
+    std::mem::take(&mut *COMMANDS.lock().unwrap())
+}
+
+fn send_command(command: TransportCommand) {
+    COMMANDS.lock().unwrap().push(command);
+}
+
+pub fn publish_status(status: PlayerStatus) {
+    *STATUS.lock().unwrap() = status;
+}
+
+fn status() -> PlayerStatus {
+    *STATUS.lock().unwrap()
+}
+
+/// Makes the details of a newly loaded song available to the page
+pub fn publish_metadata(song_data: &SongData) {
+    let header = &song_data.song.header;
+    let stats = stats::compute(&song_data.song);
+    let metadata = SongMetadata {
+        title: song_data.title(),
+        author: decode_text(&header.song_author),
+        original_author: decode_text(&header.original_author),
+        description: decode_text(&header.description),
+        tempo: header.tempo as f32 / 100.0,
+        length_ticks: stats.length_ticks,
+        duration: stats.duration_seconds,
+        layers: song_data.song.layers.len(),
+        notes: stats.notes,
+        format: song_data.format.describe(),
+    };
+    *METADATA.lock().unwrap() = serde_json::to_string(&metadata).unwrap_or_default();
+}
+
+/// Tells the page a tick was played
+pub fn emit_tick(tick: u32) {
+    #[cfg(target_arch = "wasm32")]
+    unsafe {
+        nbs_on_tick(tick)
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    let _ = tick;
+}
+
+/// Tells the page the song reached its end
+pub fn emit_end() {
+    #[cfg(target_arch = "wasm32")]
+    unsafe {
+        nbs_on_end()
+    };
+}
+
+/// Takes back a buffer made by `nbs_alloc`
+///
+/// # Safety
+/// `ptr` must come from `nbs_alloc(len)` and not have been taken back yet.
+unsafe fn take_buffer(ptr: *mut u8, len: usize) -> Vec<u8> {
+    unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)) }.into_vec()
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn nbs_player_crate_version() -> u32 {
+    WEB_API_VERSION
+}
+
+/// Allocates `len` bytes for the page to write a song or its name into
+#[unsafe(no_mangle)]
+pub extern "C" fn nbs_alloc(len: usize) -> *mut u8 {
+    // A boxed slice's capacity is its length, which `take_buffer` relies on
+    Box::into_raw(vec![0u8; len].into_boxed_slice()) as *mut u8
+}
+
+/// Loads a song written into buffers from `nbs_alloc`, taking ownership of both
+///
+/// # Safety
+/// Both buffers must come from `nbs_alloc` with the given lengths, the name being UTF-8.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nbs_load_song(
+    bytes_ptr: *mut u8,
+    bytes_len: usize,
+    name_ptr: *mut u8,
+    name_len: usize,
+) {
+    let bytes = unsafe { take_buffer(bytes_ptr, bytes_len) };
+    let name = unsafe { take_buffer(name_ptr, name_len) };
+    queue_song(PendingSong {
+        name: String::from_utf8_lossy(&name).into_owned(),
+        bytes,
+    });
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn nbs_play() {
+    send_command(TransportCommand::Play);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn nbs_pause() {
+    send_command(TransportCommand::Pause);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn nbs_toggle_pause() {
+    send_command(TransportCommand::TogglePause);
+}
+
+/// Jumps to a position in seconds
+#[unsafe(no_mangle)]
+pub extern "C" fn nbs_seek(seconds: f32) {
+    send_command(TransportCommand::Seek(seconds));
+}
+
+/// Sets the note volume, from 0 to 1
+#[unsafe(no_mangle)]
+pub extern "C" fn nbs_set_volume(volume: f32) {
+    send_command(TransportCommand::SetVolume(volume));
+}
+
+/// Sets the playback speed, 1 being the song's tempo
+#[unsafe(no_mangle)]
+pub extern "C" fn nbs_set_speed(speed: f32) {
+    send_command(TransportCommand::SetSpeed(speed));
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn nbs_next() {
+    send_command(TransportCommand::Next);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn nbs_previous() {
+    send_command(TransportCommand::Previous);
+}
+
+/// Position in seconds
+#[unsafe(no_mangle)]
+pub extern "C" fn nbs_position() -> f32 {
+    status().position
+}
+
+/// Duration in seconds
+#[unsafe(no_mangle)]
+pub extern "C" fn nbs_duration() -> f32 {
+    status().duration
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn nbs_tick() -> f32 {
+    status().tick
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn nbs_is_paused() -> u32 {
+    status().paused as u32
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn nbs_speed() -> f32 {
+    status().speed
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn nbs_volume() -> f32 {
+    status().volume
+}
+
+/// Address of the song's metadata JSON, valid until the next song is loaded
+#[unsafe(no_mangle)]
+pub extern "C" fn nbs_metadata_ptr() -> *const u8 {
+    METADATA.lock().unwrap().as_ptr()
+}
+
+/// Length of the song's metadata JSON in bytes
+#[unsafe(no_mangle)]
+pub extern "C" fn nbs_metadata_len() -> usize {
+    METADATA.lock().unwrap().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_song_from_buffers() {
+        let song = b"song bytes";
+        let name = "archive/nyan_cat.nbs";
+        let bytes_ptr = nbs_alloc(song.len());
+        let name_ptr = nbs_alloc(name.len());
+        unsafe {
+            std::ptr::copy_nonoverlapping(song.as_ptr(), bytes_ptr, song.len());
+            std::ptr::copy_nonoverlapping(name.as_ptr(), name_ptr, name.len());
+            nbs_load_song(bytes_ptr, song.len(), name_ptr, name.len());
+        }
+
+        let pending = take_pending_song().unwrap();
+        assert_eq!(pending.name, name);
+        assert_eq!(pending.bytes, song);
+        assert!(take_pending_song().is_none());
+    }
+
+    #[test]
+    fn test_empty_buffer_is_taken_back() {
+        let ptr = nbs_alloc(0);
+        assert!(unsafe { take_buffer(ptr, 0) }.is_empty());
+    }
+
+    #[test]
+    fn test_page_controls_are_queued() {
+        nbs_pause();
+        nbs_seek(12.5);
+        nbs_set_speed(2.);
+
+        assert_eq!(
+            take_commands(),
+            vec![
+                TransportCommand::Pause,
+                TransportCommand::Seek(12.5),
+                TransportCommand::SetSpeed(2.),
+            ]
+        );
+        assert!(take_commands().is_empty());
+    }
+}
@@ -0,0 +1,251 @@
+use nbs_rs::NbsFile;
+
+/// Newest NBS version this writer knows about
+pub const LATEST_VERSION: u8 = 5;
+
+/// Little-endian writer for the NBS binary layout
+#[derive(Default)]
+pub struct NbsWriter {
+    bytes: Vec<u8>,
+}
+
+impl NbsWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_i16(&mut self, value: i16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_i32(&mut self, value: i32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Strings are an `i32` byte length followed by the bytes, without terminator
+    pub fn write_string(&mut self, value: impl AsRef<[u8]>) {
+        let value = value.as_ref();
+        self.write_i32(value.len() as i32);
+        self.bytes.extend_from_slice(value);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Serializes a song in the NBS version it was read from
+pub fn write_nbs(song: &NbsFile) -> Vec<u8> {
+    write_nbs_version(song, song.header.version as u8)
+}
+
+/// Serializes a song as the given NBS version, 0 being the classic format without version byte.
+/// Fields the version doesn't have are dropped.
+pub fn write_nbs_version(song: &NbsFile, version: u8) -> Vec<u8> {
+    let version = version.min(LATEST_VERSION);
+    let header = &song.header;
+    let mut out = NbsWriter::new();
+
+    // Part 1: header
+    if version == 0 {
+        out.write_u16(header.song_length as u16);
+    } else {
+        out.write_u16(0); // new format marker
+        out.write_u8(version);
+        out.write_u8(header.default_instruments as u8);
+        if version >= 3 {
+            out.write_u16(header.song_length as u16);
+        }
+    }
+    out.write_u16(song.layers.len() as u16);
+    out.write_string(&header.song_name);
+    out.write_string(&header.song_author);
+    out.write_string(&header.original_author);
+    out.write_string(&header.description);
+    out.write_u16(header.tempo as u16);
+    out.write_u8(header.auto_save as u8);
+    out.write_u8(header.auto_save_duration as u8);
+    out.write_u8(header.time_signature as u8);
+    out.write_i32(header.minutes_spent as i32);
+    out.write_i32(header.left_clicks as i32);
+    out.write_i32(header.right_clicks as i32);
+    out.write_i32(header.blocks_added as i32);
+    out.write_i32(header.blocks_removed as i32);
+    out.write_string(&header.song_origin);
+    if version >= 4 {
+        out.write_u8(header.loop_on_off as u8);
+        out.write_u8(header.max_loop_count as u8);
+        out.write_u16(header.loop_start_tick as u16);
+    }
+
+    // Part 2: note blocks, as jumps from the previous tick and layer starting at -1
+    let mut notes: Vec<_> = song.notes.iter().collect();
+    notes.sort_by_key(|note| (note.tick as i64, note.layer as i64));
+
+    let mut last_tick: i64 = -1;
+    let mut last_layer: i64 = -1;
+    for note in notes {
+        let tick = note.tick as i64;
+        if tick != last_tick {
+            if last_tick >= 0 {
+                out.write_i16(0); // end of the previous tick
+            }
+            out.write_i16((tick - last_tick) as i16);
+            last_tick = tick;
+            last_layer = -1;
+        }
+
+        let layer = note.layer as i64;
+        out.write_i16((layer - last_layer) as i16);
+        last_layer = layer;
+
+        out.write_u8(note.instrument);
+        out.write_u8(note.key);
+        if version >= 4 {
+            out.write_u8(note.velocity);
+            out.write_u8(note.panning as u8);
+            out.write_i16(note.pitch);
+        }
+    }
+    if last_tick >= 0 {
+        out.write_i16(0);
+    }
+    out.write_i16(0);
+
+    // Part 3: layers
+    for layer in &song.layers {
+        out.write_string(&layer.name);
+        if version >= 4 {
+            out.write_u8(layer.lock as u8);
+        }
+        out.write_u8(layer.volume);
+        if version >= 2 {
+            out.write_u8(layer.panning as u8);
+        }
+    }
+
+    // Part 4: custom instruments
+    out.write_u8(song.instruments.len() as u8);
+    for instrument in &song.instruments {
+        out.write_string(&instrument.name);
+        out.write_string(&instrument.file);
+        out.write_u8(instrument.key);
+        out.write_u8(instrument.press_key as u8);
+    }
+
+    out.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "zip")]
+    use std::io::Read;
+
+    use nbs_rs::NbsParser;
+
+    use super::*;
+
+    fn assert_round_trip(name: &str, bytes: &[u8]) {
+        let song = NbsParser::new(bytes).parse().unwrap();
+        let written = write_nbs(&song);
+
+        assert_eq!(written.len(), bytes.len(), "{} changed size", name);
+        assert!(written == bytes, "{} is not byte-exact", name);
+    }
+
+    #[cfg(feature = "zip")]
+    fn song_from_zip(bytes: &[u8]) -> Vec<u8> {
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+        let mut song = Vec::new();
+        zip.by_name("song.nbs")
+            .unwrap()
+            .read_to_end(&mut song)
+            .unwrap();
+        song
+    }
+
+    #[test]
+    fn test_round_trip_test_assets() {
+        let songs: [(&str, &[u8]); 6] = [
+            (
+                "Bad Piggies Theme.nbs",
+                include_bytes!("../test-assets/Bad Piggies Theme.nbs"),
+            ),
+            (
+                "Metropolis of Illusion.nbs",
+                include_bytes!("../test-assets/Metropolis of Illusion.nbs"),
+            ),
+            (
+                "Note Block Megacollab.nbs",
+                include_bytes!("../test-assets/Note Block Megacollab.nbs"),
+            ),
+            ("Rush E.nbs", include_bytes!("../test-assets/Rush E.nbs")),
+            (
+                "nyan_cat.nbs",
+                include_bytes!("../test-assets/nyan_cat.nbs"),
+            ),
+            (
+                "turkish_march.nbs",
+                include_bytes!("../test-assets/turkish_march.nbs"),
+            ),
+        ];
+        for (name, bytes) in songs {
+            assert_round_trip(name, bytes);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "zip")]
+    fn test_round_trip_packs() {
+        let packs: [(&str, &[u8]); 2] = [
+            (
+                "Mesmerizer.zip",
+                include_bytes!("../test-assets/Mesmerizer.zip"),
+            ),
+            (
+                "bo en - My Time.zip",
+                include_bytes!("../test-assets/bo en - My Time.zip"),
+            ),
+        ];
+        for (name, bytes) in packs {
+            assert_round_trip(name, &song_from_zip(bytes));
+        }
+    }
+
+    #[test]
+    fn test_write_every_version() {
+        let bytes = include_bytes!("../test-assets/nyan_cat.nbs");
+        let song = NbsParser::new(bytes).parse().unwrap();
+
+        for version in 0..=LATEST_VERSION {
+            let written = write_nbs_version(&song, version);
+            let reparsed = NbsParser::new(&written).parse().unwrap();
+
+            assert_eq!(
+                reparsed.notes.len(),
+                song.notes.len(),
+                "version {}",
+                version
+            );
+            assert_eq!(
+                reparsed.layers.len(),
+                song.layers.len(),
+                "version {}",
+                version
+            );
+            assert_eq!(
+                reparsed.header.tempo, song.header.tempo,
+                "version {}",
+                version
+            );
+        }
+    }
+}